@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pycom_wrap::{lsp::request::IncommingMessage, rpc, scanner::Scanner};
+
+// Header parsing, framing and decoding must never panic, whatever the client sends
+fuzz_target!(|data: &[u8]| {
+    let _ = rpc::parse_header(data);
+    let _ = rpc::decode_message::<IncommingMessage>(data);
+
+    for message in Scanner::from_reader(data) {
+        let _ = rpc::decode_message::<IncommingMessage>(&message);
+    }
+});
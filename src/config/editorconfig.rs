@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use globset::{GlobBuilder, GlobMatcher};
+use tracing::warn;
+
+use super::{ini, Settings};
+use crate::lsp::comment_wrapper::EndOfLine;
+
+pub const FILE_NAME: &str = ".editorconfig";
+
+/// A parsed `.editorconfig` file, whose sections apply to the files below its directory.
+#[derive(Debug)]
+pub struct EditorConfig {
+    directory: PathBuf,
+    /// Whether the files in the directories above are ignored.
+    pub root: bool,
+    sections: Vec<(GlobMatcher, Vec<(String, String)>)>,
+}
+
+impl EditorConfig {
+    pub fn parse(directory: &Path, text: &str) -> EditorConfig {
+        let mut root = false;
+        let mut sections: Vec<(GlobMatcher, Vec<(String, String)>)> = Vec::new();
+
+        for (name, options) in ini::parse(text) {
+            if name.is_empty() {
+                root = options
+                    .iter()
+                    .any(|(key, value)| key == "root" && value.eq_ignore_ascii_case("true"));
+                continue;
+            }
+
+            match section_glob(&name) {
+                Some(glob) => sections.push((glob, options)),
+                None => warn!("Ignoring section [{}] of {}", name, directory.display()),
+            }
+        }
+
+        return EditorConfig {
+            directory: directory.to_path_buf(),
+            root,
+            sections,
+        };
+    }
+
+    /// Sets the properties of the sections that match `path` in `properties`, in order.
+    fn collect_properties(&self, path: &Path, properties: &mut HashMap<String, String>) {
+        let Ok(relative_path) = path.strip_prefix(&self.directory) else {
+            return;
+        };
+
+        for (glob, options) in self.sections.iter() {
+            if !glob.is_match(relative_path) {
+                continue;
+            }
+            for (key, value) in options {
+                properties.insert(key.clone(), value.to_lowercase());
+            }
+        }
+    }
+}
+
+/// A section name matches paths relative to the directory of its file, and a name without a
+/// slash matches files at any depth.
+fn section_glob(name: &str) -> Option<GlobMatcher> {
+    let pattern = match name.strip_prefix('/') {
+        Some(pattern) => pattern.to_string(),
+        None if name.contains('/') => name.to_string(),
+        None => format!("**/{}", name),
+    };
+    // A `**` that is part of a file name, as in `**.py`, also matches the directories on the way
+    let pattern = pattern
+        .split("**")
+        .enumerate()
+        .map(|(idx, part)| match idx {
+            0 => part.to_string(),
+            _ if part.is_empty() || part.starts_with('/') => format!("**{}", part),
+            _ => format!("**/*{}", part),
+        })
+        .collect::<String>();
+
+    return GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|glob| glob.compile_matcher());
+}
+
+/// The settings for the file at `path` from `editor_configs`, ordered from the outermost to the
+/// innermost directory.
+pub fn settings(editor_configs: &[&EditorConfig], path: &Path) -> Settings {
+    let mut properties: HashMap<String, String> = HashMap::new();
+    for editor_config in editor_configs {
+        editor_config.collect_properties(path, &mut properties);
+    }
+    properties.retain(|_, value| value != "unset");
+
+    let number = |key: &str| {
+        properties
+            .get(key)
+            .and_then(|value| value.parse::<usize>().ok())
+    };
+    let indent_size = number("indent_size");
+    let tab_width = number("tab_width").or(indent_size);
+
+    return Settings {
+        line_length: number("max_line_length").map(|w| w as u64),
+        indent_size: match properties.get("indent_size").map(String::as_str) {
+            Some("tab") => tab_width,
+            _ => indent_size,
+        },
+        tab_width,
+        end_of_line: match properties.get("end_of_line").map(String::as_str) {
+            Some("lf") => Some(EndOfLine::Lf),
+            Some("crlf") => Some(EndOfLine::Crlf),
+            Some("cr") => Some(EndOfLine::Cr),
+            _ => None,
+        },
+        ..Settings::default()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{settings, EditorConfig};
+    use crate::lsp::comment_wrapper::EndOfLine;
+    use std::path::Path;
+
+    #[test]
+    fn test_sections_apply_in_order() {
+        let outer = EditorConfig::parse(
+            Path::new("/project"),
+            "root = true\n\n[*]\nend_of_line = lf\nmax_line_length = 100\n\n[*.{py,pyi}]\nindent_size = 4\n\n[/tests/**.py]\nmax_line_length = off\n",
+        );
+        let inner = EditorConfig::parse(
+            Path::new("/project/src"),
+            "[legacy/*.py]\nindent_style = tab\nindent_size = tab\ntab_width = 8\nend_of_line = unset\n",
+        );
+        assert!(outer.root);
+        assert!(!inner.root);
+
+        let a = settings(&[&outer, &inner], Path::new("/project/src/a.py"));
+        assert_eq!(a.line_length, Some(100));
+        assert_eq!((a.indent_size, a.tab_width), (Some(4), Some(4)));
+        assert_eq!(a.end_of_line, Some(EndOfLine::Lf));
+
+        let legacy = settings(&[&outer, &inner], Path::new("/project/src/legacy/b.py"));
+        assert_eq!((legacy.indent_size, legacy.tab_width), (Some(8), Some(8)));
+        assert_eq!(legacy.end_of_line, None);
+
+        // The slash anchors the section to the directory of the file
+        let test = settings(
+            &[&outer, &inner],
+            Path::new("/project/tests/unit/test_a.py"),
+        );
+        assert_eq!(test.line_length, None);
+        let nested_test = settings(&[&outer], Path::new("/project/src/tests/test_a.py"));
+        assert_eq!(nested_test.line_length, Some(100));
+    }
+}
@@ -0,0 +1,78 @@
+/// The sections of an INI file in order, each with its options in order. Options before the first
+/// section are in a section without a name.
+///
+/// Option names are lowercased, and values are trimmed. Continuation lines are ignored, as none of
+/// the options that are read span lines.
+pub fn parse(text: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut sections: Vec<(String, Vec<(String, String)>)> = vec![(String::new(), vec![])];
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';']) || line.starts_with([' ', '\t']) {
+            continue;
+        }
+
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+            sections.push((name.trim().to_string(), vec![]));
+            continue;
+        }
+
+        let Some(separator) = trimmed.find(['=', ':']) else {
+            continue;
+        };
+        if let Some((_, options)) = sections.last_mut() {
+            options.push((
+                trimmed[..separator].trim().to_lowercase(),
+                trimmed[separator + 1..].trim().to_string(),
+            ));
+        }
+    }
+
+    return sections;
+}
+
+/// The value of the last `key` in the first of `section_names` that sets it. Dashes and
+/// underscores in `key` are interchangeable, as they are for flake8.
+pub fn find<'a>(
+    sections: &'a [(String, Vec<(String, String)>)],
+    section_names: &[&str],
+    key: &str,
+) -> Option<&'a str> {
+    let key = key.replace('_', "-");
+
+    for section_name in section_names {
+        let value = sections
+            .iter()
+            .filter(|(name, _)| name == section_name)
+            .flat_map(|(_, options)| options.iter())
+            .rfind(|(option, _)| option.replace('_', "-") == key);
+        if let Some((_, value)) = value {
+            return Some(value);
+        }
+    }
+
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, parse};
+
+    #[test]
+    fn test_find_option() {
+        let sections = parse(
+            "; comment\n[metadata]\nname = a\n\n[flake8]\nmax_line_length = 100\nextend-ignore =\n    E203\n[pycodestyle]\nmax-line-length: 90\n",
+        );
+
+        assert_eq!(
+            find(&sections, &["flake8", "pycodestyle"], "max-line-length"),
+            Some("100")
+        );
+        assert_eq!(
+            find(&sections, &["pycodestyle"], "max-line-length"),
+            Some("90")
+        );
+        assert_eq!(find(&sections, &["flake8"], "extend-ignore"), Some(""));
+        assert_eq!(find(&sections, &["flake8"], "max-doc-length"), None);
+    }
+}
@@ -0,0 +1,785 @@
+//! Settings that projects keep in their own files.
+//!
+//! The project of a document is the nearest directory, starting at the directory of the document
+//! and walking up, that contains a `pycom-wrap.toml` or a `pyproject.toml`, or else one of the
+//! files of other tools that line lengths fall back to. In a workspace folder, only the
+//! directories up to the folder itself are searched, so that every folder keeps its own
+//! configuration. Its settings are read from a
+//! `pycom-wrap.toml`, which holds them at its top level, or else from the `[tool.pycom-wrap]`
+//! table of its `pyproject.toml`:
+//!
+//! ```toml
+//! [tool.pycom-wrap]
+//! line-length = 88
+//! max-doc-length = 72
+//! docstring-style = "google"
+//! strategy = "reflow"
+//! exclude = ["migrations/*", "*_pb2.py"]
+//! pragma-prefixes = ["noqa", "type:", "NOSONAR"]
+//! ```
+//!
+//! Exclude globs are matched against the path of a document relative to the project directory.
+//!
+//! Any of the options, except `exclude`, can be set again for some of the documents of a project
+//! in `[[tool.pycom-wrap.overrides]]` tables, or `[[overrides]]` in `pycom-wrap.toml`. An override
+//! applies to the documents that match one of its `include` globs and none of its `exclude` globs,
+//! and later overrides take precedence over earlier ones:
+//!
+//! ```toml
+//! [[tool.pycom-wrap.overrides]]
+//! include = ["tests/**"]
+//! exclude = ["tests/fixtures/**"]
+//! line-length = 100
+//! ```
+//!
+//! Editors can set the same options, with camelCase names such as `lineLength`, which take
+//! precedence over the settings of the project, except for `exclude`.
+//! `format-on-save` only matters to editors, which wrap documents when they are saved unless it
+//! is `false`.
+//!
+//! When the line length is not set, it is taken from the first of these that sets it:
+//!
+//! 1. `line-length` in `[tool.black]` of `pyproject.toml`
+//! 2. `line-length` in `[tool.ruff]` of `pyproject.toml`
+//! 3. `max-line-length` in `[flake8]` or `[pycodestyle]` of `setup.cfg`, `tox.ini` or `.flake8`,
+//!    in that order
+//!
+//! 4. `max_line_length` in the `.editorconfig` files of the document
+//!
+//! Likewise, the maximum doc length, which is pycodestyle's limit for comments and docstrings,
+//! falls back to `max-doc-length` in `[tool.ruff.lint.pycodestyle]` of `pyproject.toml`, and then
+//! to `max-doc-length` in the same files and sections as `max-line-length`.
+//!
+//! The `.editorconfig` files of a document are those in its directory and the directories above,
+//! up to the first one with `root = true`. Besides the line length, their `indent_size`,
+//! `tab_width` and `end_of_line` are used when they are not set in the project.
+
+mod editorconfig;
+mod ini;
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::lsp::comment_wrapper::{CommentWrapper, DocstringStyle, EndOfLine, WrapStrategy};
+
+use editorconfig::EditorConfig;
+pub use editorconfig::FILE_NAME as EDITORCONFIG_FILE_NAME;
+
+/// The files that configure a project.
+pub const CONFIG_FILE_NAMES: &[&str] = &[
+    "pycom-wrap.toml",
+    "pyproject.toml",
+    "setup.cfg",
+    "tox.ini",
+    ".flake8",
+];
+
+/// The files that mark the directory of a project.
+const ROOT_FILE_NAMES: &[&str] = &["pycom-wrap.toml", "pyproject.toml"];
+
+/// The files of other tools that the line lengths fall back to, by precedence.
+const INI_FILE_NAMES: &[&str] = &["setup.cfg", "tox.ini", ".flake8"];
+const INI_SECTION_NAMES: &[&str] = &["flake8", "pycodestyle"];
+
+/// Options of the wrapper, where every option that is not set keeps its current value. Project
+/// files name them in kebab-case and editors in camelCase.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Settings {
+    #[serde(alias = "lineLength")]
+    pub line_length: Option<u64>,
+    #[serde(alias = "maxDocLength")]
+    pub max_doc_length: Option<u64>,
+    #[serde(alias = "docstringStyle")]
+    pub docstring_style: Option<DocstringStyle>,
+    pub strategy: Option<WrapStrategy>,
+    pub exclude: Option<Vec<String>>,
+    /// Replaces the default pragma prefixes.
+    #[serde(alias = "pragmaPrefixes")]
+    pub pragma_prefixes: Option<Vec<String>>,
+    #[serde(alias = "tabWidth")]
+    pub tab_width: Option<usize>,
+    #[serde(alias = "indentSize")]
+    pub indent_size: Option<usize>,
+    #[serde(alias = "endOfLine")]
+    pub end_of_line: Option<EndOfLine>,
+    /// Whether editors wrap documents when they are saved, which they do unless it is `false`.
+    #[serde(alias = "formatOnSave")]
+    pub format_on_save: Option<bool>,
+}
+
+impl Settings {
+    /// Returns `comment_wrapper` with the options that are set overridden.
+    pub fn apply(&self, comment_wrapper: &CommentWrapper) -> CommentWrapper {
+        return CommentWrapper {
+            max_line_length: self.line_length.unwrap_or(comment_wrapper.max_line_length),
+            max_doc_length: self.max_doc_length.or(comment_wrapper.max_doc_length),
+            docstring_style: self
+                .docstring_style
+                .unwrap_or(comment_wrapper.docstring_style),
+            strategy: self.strategy.unwrap_or(comment_wrapper.strategy),
+            pragma_prefixes: self
+                .pragma_prefixes
+                .clone()
+                .unwrap_or_else(|| comment_wrapper.pragma_prefixes.clone()),
+            tab_width: self.tab_width.unwrap_or(comment_wrapper.tab_width),
+            indent_size: self.indent_size.unwrap_or(comment_wrapper.indent_size),
+            end_of_line: self.end_of_line.or(comment_wrapper.end_of_line),
+        };
+    }
+
+    /// Takes the options that are not set from `fallback`.
+    pub fn or(self, fallback: Settings) -> Settings {
+        return Settings {
+            line_length: self.line_length.or(fallback.line_length),
+            max_doc_length: self.max_doc_length.or(fallback.max_doc_length),
+            docstring_style: self.docstring_style.or(fallback.docstring_style),
+            strategy: self.strategy.or(fallback.strategy),
+            exclude: self.exclude.or(fallback.exclude),
+            pragma_prefixes: self.pragma_prefixes.or(fallback.pragma_prefixes),
+            tab_width: self.tab_width.or(fallback.tab_width),
+            indent_size: self.indent_size.or(fallback.indent_size),
+            end_of_line: self.end_of_line.or(fallback.end_of_line),
+            format_on_save: self.format_on_save.or(fallback.format_on_save),
+        };
+    }
+}
+
+/// The settings in the files of a project, before the options of other tools are taken into
+/// account.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ProjectSettings {
+    #[serde(flatten)]
+    settings: Settings,
+    overrides: Vec<OverrideSettings>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct OverrideSettings {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PyProject {
+    tool: PyProjectTools,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PyProjectTools {
+    #[serde(rename = "pycom-wrap")]
+    pycom_wrap: ProjectSettings,
+    black: LineLengthTable,
+    ruff: RuffTable,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+struct LineLengthTable {
+    line_length: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+struct RuffTable {
+    line_length: Option<u64>,
+    lint: RuffLintTable,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RuffLintTable {
+    pycodestyle: PycodestyleTable,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+struct PycodestyleTable {
+    max_doc_length: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Glob(globset::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ConfigError::Io(err) => write!(f, "Could not read file: {}", err),
+            ConfigError::Toml(err) => write!(f, "Invalid TOML: {}", err),
+            ConfigError::Glob(err) => write!(f, "Invalid glob: {}", err),
+        };
+    }
+}
+
+/// Settings that apply to some of the documents of a project.
+#[derive(Debug)]
+struct Override {
+    include: GlobSet,
+    exclude: GlobSet,
+    settings: Settings,
+}
+
+/// The settings of the project in `root`.
+#[derive(Debug)]
+pub struct ProjectConfig {
+    pub root: PathBuf,
+    /// The settings of the documents that no override applies to.
+    pub settings: Settings,
+    exclude: GlobSet,
+    overrides: Vec<Override>,
+}
+
+impl ProjectConfig {
+    pub fn load(root: &Path) -> Result<ProjectConfig, ConfigError> {
+        let pyproject = match read_optional(&root.join("pyproject.toml"))? {
+            Some(text) => toml::from_str::<PyProject>(&text).map_err(ConfigError::Toml)?,
+            None => PyProject::default(),
+        };
+        let ProjectSettings {
+            mut settings,
+            overrides,
+        } = match read_optional(&root.join("pycom-wrap.toml"))? {
+            Some(text) => toml::from_str::<ProjectSettings>(&text).map_err(ConfigError::Toml)?,
+            None => pyproject.tool.pycom_wrap,
+        };
+
+        let ini_files = INI_FILE_NAMES
+            .iter()
+            .map(|file_name| read_optional(&root.join(file_name)))
+            .collect::<Result<Vec<Option<String>>, ConfigError>>()?;
+        let ini_files: Vec<_> = ini_files.iter().flatten().map(|w| ini::parse(w)).collect();
+        let ini_option = |key: &str| {
+            ini_files.iter().find_map(|sections| {
+                let value = ini::find(sections, INI_SECTION_NAMES, key)?;
+                match value.parse::<u64>() {
+                    Ok(value) => Some(value),
+                    Err(_) => {
+                        warn!("Ignoring {} = {} in {}", key, value, root.display());
+                        None
+                    }
+                }
+            })
+        };
+
+        settings.line_length = settings
+            .line_length
+            .or(pyproject.tool.black.line_length)
+            .or(pyproject.tool.ruff.line_length)
+            .or_else(|| ini_option("max-line-length"));
+        settings.max_doc_length = settings
+            .max_doc_length
+            .or(pyproject.tool.ruff.lint.pycodestyle.max_doc_length)
+            .or_else(|| ini_option("max-doc-length"));
+
+        let overrides = overrides
+            .into_iter()
+            .map(|w| {
+                return Ok(Override {
+                    include: glob_set(&w.include)?,
+                    exclude: glob_set(&w.exclude)?,
+                    settings: w.settings,
+                });
+            })
+            .collect::<Result<Vec<Override>, ConfigError>>()?;
+
+        return Ok(ProjectConfig {
+            root: root.to_path_buf(),
+            exclude: glob_set(settings.exclude.as_deref().unwrap_or_default())?,
+            settings,
+            overrides,
+        });
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        return match path.strip_prefix(&self.root) {
+            Ok(relative_path) => self.exclude.is_match(relative_path),
+            Err(_) => false,
+        };
+    }
+
+    /// The settings of the document at `path`, with the overrides that apply to it.
+    pub fn settings_for(&self, path: &Path) -> Settings {
+        let Ok(relative_path) = path.strip_prefix(&self.root) else {
+            return self.settings.clone();
+        };
+
+        return self
+            .overrides
+            .iter()
+            .filter(|w| w.include.is_match(relative_path) && !w.exclude.is_match(relative_path))
+            .fold(self.settings.clone(), |settings, w| {
+                w.settings.clone().or(settings)
+            });
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, ConfigError> {
+    let mut glob_set = GlobSetBuilder::new();
+    for pattern in patterns {
+        glob_set.add(Glob::new(pattern).map_err(ConfigError::Glob)?);
+    }
+
+    return glob_set.build().map_err(ConfigError::Glob);
+}
+
+/// The text of the file at `path`, or `None` if there is no such file.
+fn read_optional(path: &Path) -> Result<Option<String>, ConfigError> {
+    return match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(ConfigError::Io(err)),
+    };
+}
+
+/// The directory of the project that the file at `path` belongs to, which is not above `folder`.
+/// The files of other tools only mark a project when no `ROOT_FILE_NAMES` do, so that they never
+/// hide the configuration of a parent directory.
+pub fn find_project_root(path: &Path, folder: Option<&Path>) -> Option<PathBuf> {
+    let find = |file_names: &[&str]| {
+        return path
+            .ancestors()
+            .skip(1)
+            .take_while(|directory| folder.is_none_or(|folder| directory.starts_with(folder)))
+            .find(|directory| {
+                file_names
+                    .iter()
+                    .any(|file_name| directory.join(file_name).is_file())
+            })
+            .map(Path::to_path_buf);
+    };
+
+    return find(ROOT_FILE_NAMES).or_else(|| find(INI_FILE_NAMES));
+}
+
+/// The modification time and size of configuration files, which tell whether the files changed
+/// since they were read.
+type Fingerprint = Vec<Option<(Option<SystemTime>, u64)>>;
+
+fn fingerprint(directory: &Path, file_names: &[&str]) -> Fingerprint {
+    return file_names
+        .iter()
+        .map(|file_name| {
+            fs::metadata(directory.join(file_name))
+                .ok()
+                .map(|metadata| (metadata.modified().ok(), metadata.len()))
+        })
+        .collect();
+}
+
+struct CacheEntry<T> {
+    fingerprint: Fingerprint,
+    /// `None` if the files are invalid, so that they are only reported once.
+    config: Option<Arc<T>>,
+}
+
+/// Parsed configuration files by directory, which are read again once they change on disk or
+/// are invalidated.
+#[derive(Default)]
+pub struct ConfigCache {
+    entries: Mutex<HashMap<PathBuf, CacheEntry<ProjectConfig>>>,
+    editor_configs: Mutex<HashMap<PathBuf, CacheEntry<EditorConfig>>>,
+}
+
+impl ConfigCache {
+    pub fn new() -> ConfigCache {
+        return ConfigCache::default();
+    }
+
+    /// The configuration of the project that the file at `path` in the workspace `folder` belongs
+    /// to.
+    pub fn get(&self, path: &Path, folder: Option<&Path>) -> Option<Arc<ProjectConfig>> {
+        let root = find_project_root(path, folder)?;
+        let fingerprint = fingerprint(&root, CONFIG_FILE_NAMES);
+
+        // Workers share the cache, so it is only locked while the map is accessed
+        if let Some(entry) = self
+            .entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&root)
+        {
+            if entry.fingerprint == fingerprint {
+                return entry.config.clone();
+            }
+        }
+
+        debug!("Reading configuration of {}", root.display());
+        let config = match ProjectConfig::load(&root) {
+            Ok(config) => Some(Arc::new(config)),
+            Err(err) => {
+                warn!("Ignoring configuration of {}: {}", root.display(), err);
+                None
+            }
+        };
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(
+                root,
+                CacheEntry {
+                    fingerprint,
+                    config: config.clone(),
+                },
+            );
+
+        return config;
+    }
+
+    /// The `.editorconfig` files that apply to the file at `path`, from the outermost to the
+    /// innermost directory.
+    fn editor_configs(&self, path: &Path) -> Vec<Arc<EditorConfig>> {
+        let mut editor_configs: Vec<Arc<EditorConfig>> = Vec::new();
+
+        for directory in path.ancestors().skip(1) {
+            let fingerprint = fingerprint(directory, &[EDITORCONFIG_FILE_NAME]);
+            if fingerprint[0].is_none() {
+                continue;
+            }
+
+            let cached = self
+                .editor_configs
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .get(directory)
+                .filter(|entry| entry.fingerprint == fingerprint)
+                .map(|entry| entry.config.clone());
+            let editor_config = match cached {
+                Some(editor_config) => editor_config,
+                None => {
+                    let editor_config =
+                        match fs::read_to_string(directory.join(EDITORCONFIG_FILE_NAME)) {
+                            Ok(text) => Some(Arc::new(EditorConfig::parse(directory, &text))),
+                            Err(err) => {
+                                warn!("Ignoring .editorconfig of {}: {}", directory.display(), err);
+                                None
+                            }
+                        };
+                    self.editor_configs
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .insert(
+                            directory.to_path_buf(),
+                            CacheEntry {
+                                fingerprint,
+                                config: editor_config.clone(),
+                            },
+                        );
+                    editor_config
+                }
+            };
+
+            if let Some(editor_config) = editor_config {
+                let root = editor_config.root;
+                editor_configs.push(editor_config);
+                if root {
+                    break;
+                }
+            }
+        }
+        editor_configs.reverse();
+
+        return editor_configs;
+    }
+
+    /// The settings of the file at `path` in the workspace `folder`, from its project and then
+    /// its `.editorconfig` files, or `None` if its project excludes it.
+    pub fn settings(&self, path: &Path, folder: Option<&Path>) -> Option<Settings> {
+        let editor_configs = self.editor_configs(path);
+        let editor_settings = editorconfig::settings(
+            &editor_configs.iter().map(Arc::as_ref).collect::<Vec<_>>(),
+            path,
+        );
+
+        return match self.get(path, folder) {
+            Some(config) if config.is_excluded(path) => None,
+            Some(config) => Some(config.settings_for(path).or(editor_settings)),
+            None => Some(editor_settings),
+        };
+    }
+
+    /// Forgets the configuration file at `path`, for changes that its metadata does not show.
+    pub fn invalidate(&self, path: &Path) {
+        let Some(directory) = path.parent() else {
+            return;
+        };
+
+        match path.file_name().and_then(|w| w.to_str()) {
+            Some(EDITORCONFIG_FILE_NAME) => {
+                self.editor_configs
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .remove(directory);
+            }
+            _ => {
+                self.entries
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .remove(directory);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_project_root, ConfigCache, Settings};
+    use crate::lsp::comment_wrapper::{CommentWrapper, DocstringStyle, EndOfLine, WrapStrategy};
+    use std::{fs, path::PathBuf};
+
+    fn project_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-config-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("src/migrations")).unwrap();
+        return directory;
+    }
+
+    #[test]
+    fn test_settings_from_pyproject() {
+        let directory = project_directory("pyproject");
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[project]\nname = \"a\"\n\n[tool.pycom-wrap]\nline-length = 100\ndocstring-style = \"google\"\nstrategy = \"split\"\nexclude = [\"src/migrations/*\"]\npragma-prefixes = [\"NOSONAR\"]\n",
+        )
+        .unwrap();
+
+        let cache = ConfigCache::new();
+        let config = cache.get(&directory.join("src/a.py"), None).unwrap();
+        assert_eq!(config.root, directory);
+        assert_eq!(
+            config.settings.apply(&CommentWrapper::default()),
+            CommentWrapper {
+                max_line_length: 100,
+                max_doc_length: None,
+                docstring_style: DocstringStyle::Google,
+                strategy: WrapStrategy::Split,
+                pragma_prefixes: vec!["NOSONAR".to_string()],
+                ..CommentWrapper::default()
+            }
+        );
+        assert!(config.is_excluded(&directory.join("src/migrations/0001_initial.py")));
+        assert!(!config.is_excluded(&directory.join("src/a.py")));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_nearest_project_is_used() {
+        let directory = project_directory("nearest");
+        fs::write(directory.join("pyproject.toml"), "").unwrap();
+        fs::write(
+            directory.join("src/pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 50\n",
+        )
+        .unwrap();
+        fs::write(directory.join("src/pycom-wrap.toml"), "line-length = 60\n").unwrap();
+
+        assert_eq!(
+            find_project_root(&directory.join("src/migrations/a.py"), None),
+            Some(directory.join("src"))
+        );
+        assert_eq!(
+            find_project_root(&directory.join("a.py"), None),
+            Some(directory.clone())
+        );
+        // Directories above the workspace folder are not searched
+        assert_eq!(
+            find_project_root(
+                &directory.join("src/migrations/a.py"),
+                Some(&directory.join("src/migrations"))
+            ),
+            None
+        );
+
+        let config = ConfigCache::new()
+            .get(&directory.join("src/a.py"), None)
+            .unwrap();
+        assert_eq!(config.settings.line_length, Some(60));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_files_of_other_tools_do_not_hide_the_project() {
+        let directory = project_directory("nested-ini");
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 50\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("src/tox.ini"),
+            "[flake8]\nmax-line-length = 120\n",
+        )
+        .unwrap();
+
+        let config = ConfigCache::new()
+            .get(&directory.join("src/a.py"), None)
+            .unwrap();
+        assert_eq!(config.root, directory);
+        assert_eq!(config.settings.line_length, Some(50));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_cache_is_invalidated_when_file_changes() {
+        let directory = project_directory("cache");
+        let config_file = directory.join("pycom-wrap.toml");
+        let document = directory.join("a.py");
+        fs::write(&config_file, "line-length = 60\n").unwrap();
+
+        let cache = ConfigCache::new();
+        let line_length =
+            |cache: &ConfigCache| cache.get(&document, None).unwrap().settings.line_length;
+        assert_eq!(line_length(&cache), Some(60));
+
+        // The size of the file changes
+        fs::write(&config_file, "line-length = 100\n").unwrap();
+        assert_eq!(line_length(&cache), Some(100));
+
+        // Neither the size nor, within its resolution, the modification time changes
+        fs::write(&config_file, "line-length = 120\n").unwrap();
+        cache.invalidate(&config_file);
+        assert_eq!(line_length(&cache), Some(120));
+
+        // Invalid files are ignored
+        fs::write(&config_file, "line-length = \"wide\"\n").unwrap();
+        assert!(cache.get(&document, None).is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_unset_settings_keep_defaults() {
+        let wrapper = CommentWrapper::default();
+        assert_eq!(Settings::default().apply(&wrapper), wrapper);
+    }
+
+    #[test]
+    fn test_line_length_falls_back_to_other_tools() {
+        let directory = project_directory("fallback");
+        let document = directory.join("a.py");
+        let cache = ConfigCache::new();
+        let lengths = |cache: &ConfigCache| {
+            let settings = cache.get(&document, None).unwrap().settings.clone();
+            (settings.line_length, settings.max_doc_length)
+        };
+
+        fs::write(
+            directory.join(".flake8"),
+            "[flake8]\nmax-line-length = 120\nmax-doc-length = 72\n",
+        )
+        .unwrap();
+        assert_eq!(lengths(&cache), (Some(120), Some(72)));
+
+        fs::write(
+            directory.join("tox.ini"),
+            "[tox]\nenvlist = py3\n\n[pycodestyle]\nmax_line_length = 110\n",
+        )
+        .unwrap();
+        assert_eq!(lengths(&cache), (Some(110), Some(72)));
+
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.ruff]\nline-length = 100\n\n[tool.ruff.lint.pycodestyle]\nmax-doc-length = 80\n",
+        )
+        .unwrap();
+        assert_eq!(lengths(&cache), (Some(100), Some(80)));
+
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.black]\nline-length = 88\n\n[tool.ruff]\nline-length = 100\n",
+        )
+        .unwrap();
+        assert_eq!(lengths(&cache), (Some(88), Some(72)));
+
+        fs::write(directory.join("pycom-wrap.toml"), "line-length = 79\n").unwrap();
+        assert_eq!(lengths(&cache), (Some(79), Some(72)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_overrides_apply_to_matching_documents() {
+        let directory = project_directory("overrides");
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 79\ndocstring-style = \"google\"\n\n[[tool.pycom-wrap.overrides]]\ninclude = [\"tests/**\"]\nexclude = [\"tests/fixtures/**\"]\nline-length = 100\n\n[[tool.pycom-wrap.overrides]]\ninclude = [\"tests/test_legacy.py\"]\nline-length = 120\nstrategy = \"split\"\n",
+        )
+        .unwrap();
+
+        let cache = ConfigCache::new();
+        let settings = |file_name: &str| cache.settings(&directory.join(file_name), None).unwrap();
+        assert_eq!(settings("src/a.py").line_length, Some(79));
+        assert_eq!(settings("tests/fixtures/a.py").line_length, Some(79));
+
+        let test = settings("tests/unit/test_a.py");
+        assert_eq!(test.line_length, Some(100));
+        assert_eq!(test.docstring_style, Some(DocstringStyle::Google));
+
+        let legacy = settings("tests/test_legacy.py");
+        assert_eq!(legacy.line_length, Some(120));
+        assert_eq!(legacy.strategy, Some(WrapStrategy::Split));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_editorconfig_applies_below_project_settings() {
+        let directory = project_directory("editorconfig");
+        let document = directory.join("src/a.py");
+        fs::write(
+            directory.join(".editorconfig"),
+            "root = true\n\n[*.py]\nmax_line_length = 120\nindent_size = 2\nend_of_line = crlf\n",
+        )
+        .unwrap();
+
+        let cache = ConfigCache::new();
+        let settings = cache.settings(&document, None).unwrap();
+        assert_eq!(settings.line_length, Some(120));
+        assert_eq!(
+            (settings.indent_size, settings.tab_width),
+            (Some(2), Some(2))
+        );
+        assert_eq!(settings.end_of_line, Some(EndOfLine::Crlf));
+
+        fs::write(
+            directory.join("src/.editorconfig"),
+            "[a.py]\nindent_size = 3\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 88\nexclude = [\"src/gen_*.py\"]\n",
+        )
+        .unwrap();
+        let settings = cache.settings(&document, None).unwrap();
+        assert_eq!(settings.line_length, Some(88));
+        assert_eq!(settings.indent_size, Some(3));
+        assert!(cache
+            .settings(&directory.join("src/gen_a.py"), None)
+            .is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -2,4 +2,14 @@ pub const JSON_RPC_DELIMITER: &str = "\r\n\r\n";
 pub const JSON_RPC_DELIMITER_BYTES: &[u8] = b"\r\n\r\n";
 pub const CONTENT_LENGTH_LABEL: &str = "Content-Length: ";
 pub const CONTENT_LENGTH_LABEL_BYTES: &[u8] = CONTENT_LENGTH_LABEL.as_bytes();
+/// Larger messages are rejected rather than buffered.
+pub const MAX_CONTENT_LENGTH: usize = 1 << 28;
+/// Longer header lines are skipped as corrupt data.
+pub const MAX_HEADER_LINE_LENGTH: usize = 1 << 12;
 pub const JSON_RPC_VERSION: &str = "2.0";
+pub const DEFAULT_MAX_LINE_LENGTH: u64 = 79;
+/// The section of the editor settings that holds the settings of the server.
+pub const CONFIGURATION_SECTION: &str = "pycomWrap";
+pub const WRAP_FILE_COMMAND: &str = "pycomWrap.wrapFile";
+pub const UNWRAP_BLOCK_COMMAND: &str = "pycomWrap.unwrapBlock";
+pub const CHECK_WORKSPACE_COMMAND: &str = "pycomWrap.checkWorkspace";
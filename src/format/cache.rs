@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::lsp::comment_wrapper::CommentWrapper;
+
+/// Each version has its own file, as a new version may wrap the same file differently, and so
+/// does each format of the file, which the last part names.
+const FILE_NAME: &str = concat!("cache-", env!("CARGO_PKG_VERSION"), "-xxh3");
+/// Keys must not change between runs, so the seed is fixed.
+const SEED: u64 = 0x7079_636f_6d77_7270;
+/// Beyond this many entries, only the files of the last run are kept.
+const MAX_ENTRIES: usize = 100_000;
+
+/// The keys of the files that were already wrapped, by path, so that later runs can skip the
+/// files that did not change since.
+#[derive(Debug, Default)]
+pub struct Cache {
+    file: PathBuf,
+    entries: HashMap<PathBuf, u64>,
+    /// The files that this run looked up or wrapped.
+    seen: HashSet<PathBuf>,
+}
+
+impl Cache {
+    /// Reads the cache of this version in `directory`. A missing or unreadable cache is empty.
+    pub fn load(directory: &Path) -> Cache {
+        let file = directory.join(FILE_NAME);
+        let entries = match fs::read_to_string(&file) {
+            Ok(text) => text
+                .lines()
+                .filter_map(|line| {
+                    let (key, path) = line.split_once('\t')?;
+                    Some((PathBuf::from(path), u64::from_str_radix(key, 16).ok()?))
+                })
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                warn!("Ignoring cache {}: {}", file.display(), err);
+                HashMap::new()
+            }
+        };
+
+        return Cache {
+            file,
+            entries,
+            seen: HashSet::new(),
+        };
+    }
+
+    /// Whether the file at `path` was wrapped when it had the content and settings of `key`.
+    pub fn contains(&mut self, path: &Path, key: u64) -> bool {
+        self.seen.insert(path.to_path_buf());
+        return self.entries.get(path) == Some(&key);
+    }
+
+    pub fn insert(&mut self, path: PathBuf, key: u64) {
+        self.seen.insert(path.clone());
+        self.entries.insert(path, key);
+    }
+
+    /// Writes the cache to a temporary file first, so that concurrent runs never read half of it.
+    /// The files of other runs are kept while they exist, unless there are too many of them.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(directory) = self.file.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let keep_unseen = self.entries.len() <= MAX_ENTRIES;
+        let mut text = String::new();
+        for (path, key) in self.entries.iter() {
+            let is_kept = self.seen.contains(path) || (keep_unseen && path.is_file());
+            if !is_kept {
+                continue;
+            }
+            // Paths that do not fit on a line are wrapped again on every run
+            let Some(path) = path.to_str().filter(|w| !w.contains('\n')) else {
+                continue;
+            };
+            text.push_str(&format!("{:016x}\t{}\n", key, path));
+        }
+        let temporary_file =
+            self.file
+                .with_file_name(format!("{}.{}.tmp", FILE_NAME, std::process::id()));
+        fs::write(&temporary_file, text)?;
+
+        return fs::rename(temporary_file, &self.file);
+    }
+}
+
+/// The key of `text` wrapped by `comment_wrapper`, which changes with either of them, and
+/// stays the same across runs of the same version.
+pub fn key(comment_wrapper: &CommentWrapper, text: &str) -> u64 {
+    let mut hasher = Xxh3::with_seed(SEED);
+    // The debug format holds every option, and does not change within a version
+    hasher.update(format!("{:?}", comment_wrapper).as_bytes());
+    hasher.update(&[0]);
+    hasher.update(text.as_bytes());
+
+    return hasher.digest();
+}
+
+/// `$PYCOM_WRAP_CACHE_DIR`, or else the `pycom-wrap` directory in the cache directory of the
+/// user.
+pub fn default_directory() -> Option<PathBuf> {
+    if let Some(directory) = env::var_os("PYCOM_WRAP_CACHE_DIR") {
+        return Some(PathBuf::from(directory));
+    }
+
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+
+    return Some(cache_home.join("pycom-wrap"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key, Cache};
+    use crate::lsp::comment_wrapper::CommentWrapper;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn test_cache_round_trip() {
+        let directory = std::env::temp_dir().join(format!(
+            "pycom-wrap-cache-{}-round-trip",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        let path = PathBuf::from("/project/a.py");
+        let comment_wrapper = CommentWrapper::default();
+        let narrow = CommentWrapper {
+            max_line_length: 40,
+            ..CommentWrapper::default()
+        };
+
+        let mut cache = Cache::load(&directory);
+        assert!(!cache.contains(&path, key(&comment_wrapper, "x = 1\n")));
+        cache.insert(path.clone(), key(&comment_wrapper, "x = 1\n"));
+        cache.save().unwrap();
+
+        let mut cache = Cache::load(&directory);
+        assert!(cache.contains(&path, key(&comment_wrapper, "x = 1\n")));
+        assert!(!cache.contains(&path, key(&comment_wrapper, "x = 2\n")));
+        assert!(!cache.contains(&path, key(&narrow, "x = 1\n")));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_cache_drops_files_that_are_gone() {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-cache-{}-prune", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let (kept, removed) = (directory.join("a.py"), directory.join("b.py"));
+        fs::write(&kept, "x = 1\n").unwrap();
+        let comment_wrapper = CommentWrapper::default();
+        let key = key(&comment_wrapper, "x = 1\n");
+
+        let mut cache = Cache::load(&directory);
+        cache.insert(kept.clone(), key);
+        cache.insert(removed.clone(), key);
+        cache.save().unwrap();
+
+        // Files that another run wrapped are kept as long as they exist
+        Cache::load(&directory).save().unwrap();
+        let mut cache = Cache::load(&directory);
+        assert!(cache.contains(&kept, key));
+        assert!(!cache.contains(&removed, key));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
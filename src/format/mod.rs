@@ -0,0 +1,337 @@
+//! Wrapping of the files on disk, for the command line.
+
+pub mod cache;
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use ignore::WalkBuilder;
+use similar::TextDiff;
+
+use self::cache::Cache;
+use crate::{
+    config::ConfigCache,
+    lsp::{comment_wrapper::CommentWrapper, lexer::lex::LexicalError},
+};
+
+/// The extensions of the files that are wrapped when a directory is walked.
+pub const PYTHON_EXTENSIONS: &[&str] = &["py", "pyi"];
+
+#[derive(Debug)]
+pub enum FormatError {
+    Walk(ignore::Error),
+    Io(PathBuf, io::Error),
+    Lexical(PathBuf, LexicalError),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            FormatError::Walk(err) => write!(f, "{}", err),
+            FormatError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            FormatError::Lexical(path, err) => write!(
+                f,
+                "{}: could not tokenize at offset {}: {:?}",
+                path.display(),
+                u32::from(err.location),
+                err.error
+            ),
+        };
+    }
+}
+
+/// Wraps files with the settings of their projects. It can be shared between threads.
+pub struct Formatter {
+    config_cache: ConfigCache,
+    /// The options that neither the project nor `.editorconfig` files set.
+    comment_wrapper: CommentWrapper,
+    cache: Option<Mutex<Cache>>,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        return Formatter {
+            config_cache: ConfigCache::new(),
+            comment_wrapper: CommentWrapper::default(),
+            cache: None,
+        };
+    }
+
+    /// Skips the files that `cache` knows to be wrapped already, and remembers the files that
+    /// are wrapped once they are.
+    pub fn with_cache(self, cache: Cache) -> Formatter {
+        return Formatter {
+            cache: Some(Mutex::new(cache)),
+            ..self
+        };
+    }
+
+    /// Writes the cache, if there is one, for later runs.
+    pub fn save_cache(&self) -> io::Result<()> {
+        return match self.cache {
+            Some(ref cache) => cache.lock().unwrap_or_else(|err| err.into_inner()).save(),
+            None => Ok(()),
+        };
+    }
+
+    /// The Python files in `paths` as `walk` finds them, with their wrappers, without the files
+    /// that projects exclude.
+    pub fn files(&self, paths: &[PathBuf]) -> Vec<Result<(PathBuf, CommentWrapper), FormatError>> {
+        return walk(paths)
+            .into_iter()
+            .filter_map(|file| match file {
+                Ok(path) => self
+                    .comment_wrapper_for(&path)
+                    .map(|comment_wrapper| Ok((path, comment_wrapper))),
+                Err(err) => Some(Err(err)),
+            })
+            .collect();
+    }
+
+    /// The wrapper for the file at `path`, or `None` if its project excludes it.
+    fn comment_wrapper_for(&self, path: &Path) -> Option<CommentWrapper> {
+        let path = absolute(path);
+        let settings = self.config_cache.settings(&path, None)?;
+
+        return Some(settings.apply(&self.comment_wrapper));
+    }
+
+    /// Returns `text`, the content of the file at `path`, with its comments and docstrings
+    /// wrapped.
+    pub fn format_text(&self, path: &Path, text: &str) -> Result<String, FormatError> {
+        let Some(comment_wrapper) = self.comment_wrapper_for(path) else {
+            return Ok(text.to_string());
+        };
+
+        return comment_wrapper
+            .format(text)
+            .map_err(|err| FormatError::Lexical(path.to_path_buf(), err));
+    }
+
+    /// The text of the file at `path`, and that text with its comments and docstrings wrapped
+    /// by `comment_wrapper`, its wrapper as `files` found it.
+    pub fn read_and_format(
+        &self,
+        path: &Path,
+        comment_wrapper: &CommentWrapper,
+    ) -> Result<(String, String), FormatError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| FormatError::Io(path.to_path_buf(), err))?;
+
+        let key = cache::key(comment_wrapper, &text);
+        if self.is_cached(path, key) {
+            return Ok((text.clone(), text));
+        }
+
+        let new_text = comment_wrapper
+            .format(&text)
+            .map_err(|err| FormatError::Lexical(path.to_path_buf(), err))?;
+        if new_text == text {
+            self.remember(path, key);
+        }
+
+        return Ok((text, new_text));
+    }
+
+    /// Wraps the file at `path` in place with `comment_wrapper`. Returns whether it changed.
+    pub fn format_file(
+        &self,
+        path: &Path,
+        comment_wrapper: &CommentWrapper,
+    ) -> Result<bool, FormatError> {
+        let (text, new_text) = self.read_and_format(path, comment_wrapper)?;
+        if new_text == text {
+            return Ok(false);
+        }
+
+        fs::write(path, &new_text).map_err(|err| FormatError::Io(path.to_path_buf(), err))?;
+        self.remember(path, cache::key(comment_wrapper, &new_text));
+        return Ok(true);
+    }
+
+    fn is_cached(&self, path: &Path, key: u64) -> bool {
+        return match self.cache {
+            Some(ref cache) => cache
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .contains(&absolute(path), key),
+            None => false,
+        };
+    }
+
+    fn remember(&self, path: &Path, key: u64) {
+        if let Some(ref cache) = self.cache {
+            cache
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(absolute(path), key);
+        }
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        return Formatter::new();
+    }
+}
+
+/// The Python files in `paths`, walking directories without the files that `.gitignore` files
+/// ignore. Files that are passed explicitly are kept whatever their extension.
+pub fn walk(paths: &[PathBuf]) -> Vec<Result<PathBuf, FormatError>> {
+    let mut files: Vec<Result<PathBuf, FormatError>> = Vec::new();
+
+    for path in paths {
+        // Ignore files apply whether or not the directory is a git repository
+        let walk = WalkBuilder::new(path)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    files.push(Err(FormatError::Walk(err)));
+                    continue;
+                }
+            };
+            if !entry.file_type().is_some_and(|w| w.is_file()) {
+                continue;
+            }
+            let is_python = entry
+                .path()
+                .extension()
+                .and_then(|w| w.to_str())
+                .is_some_and(|w| PYTHON_EXTENSIONS.contains(&w));
+            if entry.depth() == 0 || is_python {
+                files.push(Ok(entry.into_path()));
+            }
+        }
+    }
+
+    return files;
+}
+
+/// The unified diff from `text` to `new_text` of the file at `path`.
+pub fn diff(path: &Path, text: &str, new_text: &str) -> String {
+    let path = path.display().to_string();
+    return TextDiff::from_lines(text, new_text)
+        .unified_diff()
+        .header(&path, &path)
+        .to_string();
+}
+
+/// Projects are found by walking up from a file, which needs the whole path.
+fn absolute(path: &Path) -> PathBuf {
+    return std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache, diff, Cache, Formatter};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    #[test]
+    fn test_format_directory() {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-format-{}-walk", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("src/build")).unwrap();
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 12\nexclude = [\"src/gen_*.py\"]\n",
+        )
+        .unwrap();
+        fs::write(directory.join(".gitignore"), "build/\n").unwrap();
+        for file_name in [
+            "src/a.py",
+            "src/b.pyi",
+            "src/c.txt",
+            "src/gen_a.py",
+            "src/build/a.py",
+        ] {
+            fs::write(directory.join(file_name), "# aaa bbb ccc\n").unwrap();
+        }
+
+        let formatter = Formatter::new();
+        let mut files: Vec<PathBuf> = formatter
+            .files(&[directory.clone(), directory.join("src/c.txt")])
+            .into_iter()
+            .map(|file| file.unwrap().0)
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                directory.join("src/a.py"),
+                directory.join("src/b.pyi"),
+                directory.join("src/c.txt"),
+            ]
+        );
+
+        let format_file = |formatter: &Formatter, path: &Path| {
+            let comment_wrapper = formatter.comment_wrapper_for(path).unwrap();
+            formatter.format_file(path, &comment_wrapper).unwrap()
+        };
+        assert!(format_file(&formatter, &directory.join("src/a.py")));
+        assert_eq!(
+            fs::read_to_string(directory.join("src/a.py")).unwrap(),
+            "# aaa bbb\n# ccc\n"
+        );
+        assert!(!format_file(&formatter, &directory.join("src/a.py")));
+
+        // Text from stdin is configured by a path that need not exist
+        let text = "# aaa bbb ccc\n";
+        assert_eq!(
+            formatter
+                .format_text(&directory.join("src/new.py"), text)
+                .unwrap(),
+            "# aaa bbb\n# ccc\n"
+        );
+        assert_eq!(
+            formatter
+                .format_text(&directory.join("src/gen_b.py"), text)
+                .unwrap(),
+            text
+        );
+
+        // Files are skipped while neither they nor their settings change
+        let formatter = Formatter::new().with_cache(Cache::load(&directory.join("cache")));
+        assert!(format_file(&formatter, &directory.join("src/b.pyi")));
+        formatter.save_cache().unwrap();
+        let is_cached = || {
+            let formatter = Formatter::new().with_cache(Cache::load(&directory.join("cache")));
+            let path = directory.join("src/b.pyi");
+            let comment_wrapper = formatter.comment_wrapper_for(&path).unwrap();
+            let text = fs::read_to_string(&path).unwrap();
+            formatter.is_cached(&path, cache::key(&comment_wrapper, &text))
+        };
+        assert!(is_cached());
+        fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 13\nexclude = [\"src/gen_*.py\"]\n",
+        )
+        .unwrap();
+        assert!(!is_cached());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(
+            diff(
+                Path::new("a.py"),
+                "x = 1\n# aaa bbb ccc\n",
+                "x = 1\n# aaa bbb\n# ccc\n"
+            ),
+            "--- a.py\n+++ a.py\n@@ -1,2 +1,3 @@\n x = 1\n-# aaa bbb ccc\n+# aaa bbb\n+# ccc\n"
+        );
+        assert_eq!(diff(Path::new("a.py"), "x = 1\n", "x = 1\n"), "");
+    }
+}
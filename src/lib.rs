@@ -0,0 +1,9 @@
+pub mod config;
+pub mod constants;
+pub mod format;
+pub mod lsp;
+pub mod rpc;
+pub mod scanner;
+pub mod server;
+pub mod transport;
+pub mod worker_pool;
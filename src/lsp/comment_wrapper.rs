@@ -1,4 +1,4 @@
-use std::{collections::HashMap, str::Chars};
+use serde::{Deserialize, Serialize};
 
 use crate::lsp::lexer::{
     lex::TokenSpan,
@@ -7,227 +7,907 @@ use crate::lsp::lexer::{
     token::{StringKind, Token},
 };
 
+use crate::constants;
+
 use super::lexer::lex::{Lexer, LexicalError};
 
-struct Position {
-    line: u32,
-    character: u32,
+const DOCSTRING_QUOTE_LENGTH: usize = 3;
+pub const DEFAULT_PRAGMA_PREFIXES: &[&str] = &[
+    "noqa", "type:", "pragma", "fmt:", "isort:", "pylint:", "mypy:", "pyright:",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentBlockKind {
+    /// A run of `#` comments that each start their own line.
+    Comment,
+    /// A triple quoted string that makes up a statement on its own.
+    Docstring,
+}
+
+/// A group of tokens that is wrapped as one unit.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CommentBlock {
+    pub kind: CommentBlockKind,
+    pub range: TextRange,
+    /// Display column of the first character of the block.
+    pub column: usize,
+}
+
+impl CommentBlock {
+    /// The whitespace in front of the block on its first line.
+    pub fn indent<'a>(&self, source: &'a str) -> &'a str {
+        return &source[TextRange::new(line_start(source, self.range.start), self.range.start)];
+    }
+
+    /// Display width of the widest line of the block, indentation included.
+    pub fn width(&self, source: &str, tab_width: usize) -> usize {
+        return split_lines(
+            &source[TextRange::new(line_start(source, self.range.start), self.range.end)],
+        )
+        .iter()
+        .map(|line| display_width(line, tab_width))
+        .max()
+        .unwrap_or(0);
+    }
+}
+
+/// The docstring convention, which decides how fields such as arguments are laid out.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocstringStyle {
+    /// Plain paragraphs, lists and literal lines.
+    #[default]
+    Pep257,
+    /// `name (type): description` fields under sections like `Args:`.
+    Google,
+    /// `name : type` lines followed by an indented description.
+    Numpy,
+    /// `:param name: description` fields.
+    Sphinx,
+}
+
+impl DocstringStyle {
+    /// The indentation of the continuation lines of `line`, if it starts a field. They are
+    /// indented by `indent_size` more than the field.
+    fn field_hang(&self, line: &str, leading: usize, indent_size: usize) -> Option<usize> {
+        let is_field = match self {
+            DocstringStyle::Google => leading > 0 && is_google_field(line),
+            DocstringStyle::Sphinx => is_sphinx_field(line),
+            DocstringStyle::Pep257 | DocstringStyle::Numpy => false,
+        };
+
+        return match is_field {
+            true => Some(leading + indent_size),
+            false => None,
+        };
+    }
+
+    /// Whether `line` must keep its exact layout in this style.
+    fn is_literal(&self, line: &str) -> bool {
+        return match self {
+            DocstringStyle::Numpy => line.contains(" : "),
+            _ => false,
+        };
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapStrategy {
+    /// Joins the lines of a paragraph and fills them up to the maximum line length.
+    #[default]
+    Reflow,
+    /// Only breaks up lines that are too long, and never joins lines.
+    Split,
 }
 
-struct Range {
-    start: Position,
-    end: Position,
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EndOfLine {
+    Lf,
+    Crlf,
+    Cr,
 }
 
-struct TextEdit {
-    range: Range,
-    new_text: String,
+impl EndOfLine {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            EndOfLine::Lf => "\n",
+            EndOfLine::Crlf => "\r\n",
+            EndOfLine::Cr => "\r",
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommentWrapper {
+    pub max_line_length: u64,
+    /// Like pycodestyle's `max-doc-length`, the length of comment and docstring lines.
+    pub max_doc_length: Option<u64>,
+    pub docstring_style: DocstringStyle,
+    pub strategy: WrapStrategy,
+    /// Comments starting with one of these are left as they are.
+    pub pragma_prefixes: Vec<String>,
+    /// Number of columns of a tab.
+    pub tab_width: usize,
+    /// Extra indentation of the continuation lines of a docstring field, like an argument.
+    pub indent_size: usize,
+    /// Line break of blocks that get their first one, instead of the first line break of the
+    /// document.
+    pub end_of_line: Option<EndOfLine>,
 }
 
-struct CommentWrapper {
-    max_line_length: u64,
+impl Default for CommentWrapper {
+    fn default() -> Self {
+        return CommentWrapper {
+            max_line_length: constants::DEFAULT_MAX_LINE_LENGTH,
+            max_doc_length: None,
+            docstring_style: DocstringStyle::default(),
+            strategy: WrapStrategy::default(),
+            pragma_prefixes: DEFAULT_PRAGMA_PREFIXES
+                .iter()
+                .map(|w| w.to_string())
+                .collect(),
+            tab_width: 4,
+            indent_size: 4,
+            end_of_line: None,
+        };
+    }
 }
 
 impl CommentWrapper {
-    fn process(&self, source: &str) -> Result<Vec<TextEdit>, LexicalError> {
-        let lexer = Lexer::new(source.chars());
-        let mut token_groups: HashMap<TextSize, Vec<&TokenSpan>> = HashMap::new();
-
-        let tokens = lexer
-            .map(|w| w)
-            .collect::<Result<Vec<TokenSpan>, LexicalError>>()?;
-
-        for (idx, token) in tokens.iter().enumerate() {
-            if let (Token::Comment(_), ..) = token {
-                // If single line comment, go back and check if there are any other single line
-                // comments that this could be grouped with.
-                if idx == 0 {
-                    token_groups.insert(token.1.start, vec![token]);
-                    continue;
-                }
+    /// Wraps every block of `source` that needs it, returning the replaced ranges and their text.
+    pub fn process(&self, source: &str) -> Result<Vec<(TextRange, String)>, LexicalError> {
+        let blocks = self.comment_blocks(source)?;
+        let mut text_edits: Vec<(TextRange, String)> = Vec::with_capacity(blocks.len());
+
+        for block in blocks.iter() {
+            if let Some(new_text) = self.wrap_block(source, block) {
+                text_edits.push((block.range, new_text));
+            }
+        }
 
-                let mut has_encountered_nl = !tokens[..idx]
-                    .iter()
-                    .any(|e| matches!(e, (Token::Comment(_), ..)));
+        return Ok(text_edits);
+    }
 
-                for (prior_token_idx, prior_token_span) in tokens[..idx].iter().rev().enumerate() {
-                    match prior_token_span {
-                        (Token::Indent | Token::Dedent, _) if prior_token_idx != 0 => {
-                            continue;
-                        }
-                        (Token::Newline | Token::NonLogicalNewline, _)
-                            if !has_encountered_nl && prior_token_idx != 0 =>
+    /// Returns `source` with every block that needs it wrapped.
+    pub fn format(&self, source: &str) -> Result<String, LexicalError> {
+        let mut text = source.to_string();
+        for (range, new_text) in self.process(source)?.into_iter().rev() {
+            text.replace_range(range.start.to_usize()..range.end.to_usize(), &new_text);
+        }
+
+        return Ok(text);
+    }
+
+    /// Groups the comments and docstrings of `source` into the blocks that are wrapped together.
+    pub fn comment_blocks(&self, source: &str) -> Result<Vec<CommentBlock>, LexicalError> {
+        let tokens =
+            Lexer::new(source.chars()).collect::<Result<Vec<TokenSpan>, LexicalError>>()?;
+        let mut blocks: Vec<CommentBlock> = Vec::new();
+
+        for (idx, (token, text_range)) in tokens.iter().enumerate() {
+            match token {
+                // Trailing comments share their line with code, so they are left untouched.
+                Token::Comment(_) if starts_line(&tokens[..idx]) => {
+                    let column = column_at(source, text_range.start, self.tab_width);
+
+                    if let Some(CommentBlock {
+                        kind: CommentBlockKind::Comment,
+                        range,
+                        column: block_column,
+                    }) = blocks.last_mut()
+                    {
+                        if *block_column == column
+                            && is_single_line_break(
+                                &source[TextRange::new(range.end, text_range.start)],
+                            )
                         {
-                            has_encountered_nl = true;
+                            *range = range.cover(*text_range);
                             continue;
                         }
-                        (Token::Comment(_), text_range) => {
-                            if let Some(group) = token_groups.get_mut(&text_range.start) {
-                                group.push(token);
-                                break;
-                            } else if prior_token_idx != 0 {
-                                // this comment has been grouped with an even earlier comment block
-                                has_encountered_nl = false;
-                                continue;
-                            }
-                            unreachable!();
-                        }
-                        _ => {
-                            token_groups.insert(token.1.start, vec![token]);
-                            break;
-                        }
-                    };
+                    }
+
+                    blocks.push(CommentBlock {
+                        kind: CommentBlockKind::Comment,
+                        range: *text_range,
+                        column,
+                    });
                 }
-            } else if let (
                 Token::String {
                     kind: StringKind::String,
                     triple_quoted: true,
                     ..
-                },
-                ..,
-            ) = token
-            {
-                if idx == 0 {
-                    token_groups.insert(token.1.start, vec![token]);
-                    continue;
+                } if is_docstring(&tokens, idx) => {
+                    blocks.push(CommentBlock {
+                        kind: CommentBlockKind::Docstring,
+                        range: *text_range,
+                        column: column_at(source, text_range.start, self.tab_width),
+                    });
                 }
+                _ => {}
+            }
+        }
 
-                // Make sure triple quoted string is set to be a comment
-                for (previous_token_idx, previous_token) in tokens[..idx].iter().rev().enumerate() {
-                    match previous_token {
-                        (Token::Dedent | Token::Indent, _) if previous_token_idx != 0 => continue,
-                        (Token::Newline, text_range) => {
-                            token_groups.insert(text_range.start, vec![previous_token]);
-                        }
-                        _ => break,
-                    }
-                    if previous_token_idx == 0 {
-                        token_groups.insert(previous_token.1.start, vec![previous_token]);
-                    }
+        return Ok(blocks);
+    }
+
+    /// The length that lines are wrapped to, as lines must not exceed either maximum.
+    pub fn wrap_length(&self) -> u64 {
+        return match self.max_doc_length {
+            Some(max_doc_length) => max_doc_length.min(self.max_line_length),
+            None => self.max_line_length,
+        };
+    }
+
+    /// Returns the wrapped text of `block`, or `None` if wrapping would not change it.
+    pub fn wrap_block(&self, source: &str, block: &CommentBlock) -> Option<String> {
+        let max_width = (self.wrap_length() as usize).checked_sub(block.column)?;
+        let new_text = self.wrap_block_to_width(source, block, max_width)?;
+
+        if new_text == source[block.range] {
+            return None;
+        }
+
+        return Some(new_text);
+    }
+
+    /// Returns the text of `block` with every paragraph joined onto a single line, or `None` if
+    /// that would not change it.
+    pub fn unwrap_block(&self, source: &str, block: &CommentBlock) -> Option<String> {
+        // Unwrapping always joins the lines, whatever the strategy
+        let reflow = CommentWrapper {
+            strategy: WrapStrategy::Reflow,
+            ..self.clone()
+        };
+        let new_text = reflow.wrap_block_to_width(source, block, usize::MAX)?;
+
+        if new_text == source[block.range] {
+            return None;
+        }
+
+        return Some(new_text);
+    }
+
+    /// Rewraps `block` so that its lines, counted from the start of the block, are at most
+    /// `max_width` characters wide. Returns `None` if there is no room left to wrap into.
+    pub fn wrap_block_to_width(
+        &self,
+        source: &str,
+        block: &CommentBlock,
+        max_width: usize,
+    ) -> Option<String> {
+        let text = &source[block.range];
+        let indent = block.indent(source);
+        let line_break = match (text.contains(['\n', '\r']), self.end_of_line) {
+            (true, _) => detect_line_break(text),
+            (false, Some(end_of_line)) => end_of_line.as_str(),
+            (false, None) => detect_line_break(source),
+        };
+
+        let lines = match block.kind {
+            // Make room for the "# " in front of every line
+            CommentBlockKind::Comment => {
+                wrap_comment(self, text, max_width.checked_sub(2).filter(|w| *w > 0)?)
+            }
+            CommentBlockKind::Docstring => wrap_docstring(
+                self,
+                text,
+                indent,
+                Some(max_width).filter(|w| *w > DOCSTRING_QUOTE_LENGTH)?,
+            )?,
+        };
+
+        let mut new_text = String::with_capacity(text.len());
+        for (idx, line) in lines.iter().enumerate() {
+            if idx > 0 {
+                new_text.push_str(line_break);
+                if !line.is_empty() {
+                    new_text.push_str(indent);
                 }
             }
+            new_text.push_str(line.trim_end());
         }
 
-        let mut text_edits: Vec<TextEdit> = Vec::with_capacity(token_groups.len());
-
-        let text = source.replace('\t', "    ");
-        let chars_vec: Vec<char> = text.chars().collect();
-        for (start_offset, tokens) in token_groups.iter() {
-            let leading = tokens[0];
-
-            let start_char_index = to_char_index(text.chars(), *start_offset);
-            let max_comment_length = self.max_line_length
-                - chars_vec[..start_char_index]
-                    .iter()
-                    .rev()
-                    .position(|c| matches!(c, '\n' | '\r'))
-                    .map(|w| w + 1)
-                    .unwrap_or(0) as u64;
-
-            // TODO Fix case where max_comment_length becomes negative or 0
-
-            match leading {
-                (Token::Comment(_), TextRange { start, .. }) => {
-                    assert!(tokens.iter().all(|e| matches!(e.0, Token::Comment(_))));
-                    let mut acc_text_range = TextRange::empty(*start);
-                    let mut acc_text = String::from("");
-                    for token in tokens {
-                        if let (Token::Comment(s), token_text_range) = token {
-                            acc_text_range = acc_text_range.cover(*token_text_range);
-                            acc_text += s;
-                        };
-                    }
-                }
+        return Some(new_text);
+    }
+}
 
-                (
-                    Token::String {
-                        kind: StringKind::String,
-                        triple_quoted: true,
-                        ..
-                    },
-                    ..,
-                ) => {
-                    assert!(tokens.iter().all(|e| matches!(
-                        e.0,
-                        Token::String {
-                            kind: StringKind::String,
-                            triple_quoted: true,
-                            ..
-                        }
-                    )))
-                }
+enum Segment {
+    Blank,
+    Verbatim(String),
+    Paragraph {
+        /// Indentation of the first line, in front of `lead`.
+        indent: usize,
+        lead: String,
+        /// Indentation of the continuation lines.
+        hang: usize,
+        words: Vec<String>,
+    },
+}
+
+fn wrap_comment(wrapper: &CommentWrapper, text: &str, width: usize) -> Vec<String> {
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for raw_line in split_lines(text) {
+        let raw_line = raw_line.trim();
+        let content = raw_line.strip_prefix('#').unwrap_or(raw_line);
+
+        // Shebangs, sphinx attribute comments and pragmas must keep their exact layout
+        let content_after_space = content.strip_prefix(' ').unwrap_or(content);
+        if content.starts_with(['!', ':', '#']) || is_pragma(wrapper, content_after_space) {
+            segments.push(Segment::Verbatim(raw_line.to_string()));
+            continue;
+        }
+
+        push_line(
+            wrapper,
+            None,
+            &mut segments,
+            content_after_space,
+            |verbatim| format!("# {}", verbatim),
+        );
+    }
+
+    return fill(&segments, width, "# ", "#");
+}
+
+fn wrap_docstring(
+    wrapper: &CommentWrapper,
+    text: &str,
+    indent: &str,
+    width: usize,
+) -> Option<Vec<String>> {
+    let quote = text.get(..DOCSTRING_QUOTE_LENGTH)?;
+    let inner = text.get(DOCSTRING_QUOTE_LENGTH..text.len() - DOCSTRING_QUOTE_LENGTH)?;
+    let lines = split_lines(inner);
+
+    let first_is_blank = lines[0].trim().is_empty();
+    let closes_on_own_line = lines.len() > 1 && lines[lines.len() - 1].trim().is_empty();
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for (idx, raw_line) in lines.iter().enumerate() {
+        if (idx == 0 && first_is_blank) || (idx == lines.len() - 1 && closes_on_own_line) {
+            continue;
+        }
+
+        let content = match idx {
+            0 => raw_line.trim_start(),
+            _ => raw_line
+                .strip_prefix(indent)
+                .unwrap_or(raw_line.trim_start()),
+        };
+
+        if is_docstring_literal_line(content) || wrapper.docstring_style.is_literal(content) {
+            segments.push(Segment::Verbatim(content.trim_end().to_string()));
+            continue;
+        }
+
+        push_line(
+            wrapper,
+            Some(wrapper.docstring_style),
+            &mut segments,
+            content,
+            |verbatim| verbatim.to_string(),
+        );
+    }
 
-                _ => unreachable!(),
+    // Glue the quotes onto the start of the first line and the last word, so that `fill`
+    // accounts for them. They go ahead of any bullet, which must stay inside the string.
+    if !first_is_blank {
+        match segments.first_mut() {
+            Some(Segment::Paragraph { indent, lead, .. }) => {
+                *lead = format!("{}{}{}", quote, " ".repeat(*indent), lead);
+                *indent = 0;
             }
+            _ => return None,
+        }
+    }
+
+    if !closes_on_own_line {
+        match segments.last_mut() {
+            Some(Segment::Paragraph { words, .. }) if !words.is_empty() => {
+                let last = words.pop()?;
+                words.push(format!("{}{}", last, quote));
+            }
+            _ => return None,
+        }
+    }
+
+    let mut out_lines: Vec<String> = Vec::with_capacity(lines.len());
+    if first_is_blank {
+        out_lines.push(quote.to_string());
+    }
+    out_lines.extend(fill(&segments, width, "", ""));
+    if closes_on_own_line {
+        out_lines.push(quote.to_string());
+    }
+
+    return Some(out_lines);
+}
+
+/// Classifies a line of comment text and appends it to `segments`. Docstring lines are
+/// classified according to `docstring_style`.
+fn push_line<F: Fn(&str) -> String>(
+    wrapper: &CommentWrapper,
+    docstring_style: Option<DocstringStyle>,
+    segments: &mut Vec<Segment>,
+    content: &str,
+    verbatim: F,
+) {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        segments.push(Segment::Blank);
+        return;
+    }
+
+    let leading = content.len() - content.trim_start().len();
+    let words = trimmed.split_whitespace().map(String::from);
+
+    if let Some(marker) = bullet_marker(trimmed) {
+        segments.push(Segment::Paragraph {
+            indent: leading,
+            lead: marker.to_string(),
+            hang: leading + marker.chars().count(),
+            words: trimmed[marker.len()..]
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+        });
+        return;
+    }
+
+    if let Some(hang) =
+        docstring_style.and_then(|style| style.field_hang(trimmed, leading, wrapper.indent_size))
+    {
+        segments.push(Segment::Paragraph {
+            indent: leading,
+            lead: String::new(),
+            hang,
+            words: words.collect(),
+        });
+        return;
+    }
+
+    let continues_paragraph = matches!(
+        segments.last(),
+        Some(Segment::Paragraph { hang, .. }) if *hang == leading
+    );
+    if continues_paragraph && wrapper.strategy == WrapStrategy::Reflow {
+        if let Some(Segment::Paragraph {
+            words: paragraph_words,
+            ..
+        }) = segments.last_mut()
+        {
+            paragraph_words.extend(words);
         }
+        return;
+    }
+
+    // Numpy descriptions are indented below the line that names them
+    if continues_paragraph || leading == 0 || docstring_style == Some(DocstringStyle::Numpy) {
+        segments.push(Segment::Paragraph {
+            indent: leading,
+            lead: String::new(),
+            hang: leading,
+            words: words.collect(),
+        });
+        return;
+    }
 
-        panic!();
+    segments.push(Segment::Verbatim(verbatim(content.trim_end())));
+}
+
+/// Greedily fills the paragraphs in `segments` so that no line exceeds `width`, unless a single
+/// word is wider than that. Paragraph lines are prefixed with `prefix`, which does not count
+/// towards `width`, and blank segments become `blank`.
+fn fill(segments: &[Segment], width: usize, prefix: &str, blank: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for segment in segments {
+        match segment {
+            Segment::Blank => lines.push(blank.to_string()),
+            Segment::Verbatim(line) => lines.push(line.clone()),
+            Segment::Paragraph {
+                indent,
+                lead,
+                hang,
+                words,
+            } => {
+                let mut line = format!("{}{}", " ".repeat(*indent), lead);
+                let mut line_has_words = false;
+                for word in words {
+                    let line_width = line.chars().count();
+                    if line_has_words && line_width + 1 + word.chars().count() > width {
+                        lines.push(format!("{}{}", prefix, line));
+                        line = " ".repeat(*hang);
+                        line_has_words = false;
+                    }
+                    if line_has_words {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    line_has_words = true;
+                }
+                lines.push(format!("{}{}", prefix, line));
+            }
+        }
     }
+
+    return lines;
 }
 
-fn format_multi_line_comments(mut str: String, max_comment_length: u32) -> String {
-    let lines = str.lines();
+fn bullet_marker(line: &str) -> Option<&str> {
+    for marker in ["- ", "* ", "+ "] {
+        if line.starts_with(marker) {
+            return Some(&line[..marker.len()]);
+        }
+    }
+
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && line[digits..].starts_with(". ") {
+        return Some(&line[..digits + 2]);
+    }
 
-    for line in lines {}
+    return None;
+}
 
-    panic!()
+fn is_pragma(wrapper: &CommentWrapper, content: &str) -> bool {
+    return wrapper
+        .pragma_prefixes
+        .iter()
+        .any(|prefix| content.starts_with(prefix.as_str()));
 }
 
-fn format_single_line_comments(mut str: String, max_comment_length: u32) -> String {
-    if str.starts_with('#') {
-        str = str[1..].to_string();
+/// Whether `line` looks like `name (type): description`.
+fn is_google_field(line: &str) -> bool {
+    let Some((name, description)) = line.split_once(':') else {
+        return false;
+    };
+    if !(description.is_empty() || description.starts_with(' ')) {
+        return false;
     }
 
-    str = str.trim().replace('\n', "").replace('\r', "");
+    let name = match name.split_once(" (") {
+        Some((name, annotation)) if annotation.ends_with(')') => name,
+        Some(_) => return false,
+        None => name,
+    };
+    let name = name.trim_start_matches('*');
+
+    return !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+}
 
-    return str;
+/// Whether `line` looks like `:param name: description`.
+fn is_sphinx_field(line: &str) -> bool {
+    return match line.strip_prefix(':').and_then(|w| w.split_once(':')) {
+        Some((field, _)) => field.starts_with(|c: char| c.is_alphabetic()),
+        None => false,
+    };
 }
 
-fn to_char_index<T: Iterator<Item = char>>(chars: T, utf_8_offset: TextSize) -> usize {
-    let mut utf_8_sum: TextSize = 0.into();
-    let mut num_chars: usize = 0;
+fn is_docstring_literal_line(content: &str) -> bool {
+    let trimmed = content.trim();
+    return trimmed.ends_with(':')
+        || trimmed.starts_with(">>>")
+        || trimmed.starts_with("...")
+        || trimmed.starts_with("..")
+        || (trimmed.len() > 2 && trimmed.chars().all(|c| matches!(c, '-' | '=' | '~')));
+}
 
-    for (idx, c) in chars.enumerate() {
-        if utf_8_sum >= utf_8_offset {
-            return idx;
+/// Whether all tokens on the line before the end of `tokens` are indentation.
+fn starts_line(tokens: &[TokenSpan]) -> bool {
+    for token in tokens.iter().rev() {
+        match token {
+            (Token::Indent | Token::Dedent, _) => continue,
+            (Token::Newline | Token::NonLogicalNewline, _) => return true,
+            _ => return false,
         }
-        num_chars += 1;
+    }
+
+    return true;
+}
 
-        utf_8_sum += TextSize::from(c);
+/// Whether the string at `idx` is a statement of its own that starts a module, or the body of
+/// a function or class. Other strings, such as arguments that span lines, are code.
+fn is_docstring(tokens: &[TokenSpan], idx: usize) -> bool {
+    if !matches!(tokens.get(idx + 1), None | Some((Token::Newline, _))) {
+        return false;
     }
 
-    return num_chars;
+    let is_skipped =
+        |token: &&TokenSpan| matches!(token.0, Token::Comment(_) | Token::NonLogicalNewline);
+    let mut before = tokens[..idx].iter().rev().filter(|w| !is_skipped(w));
+    return match before.next() {
+        None => true,
+        Some((Token::Indent, _)) => {
+            if !matches!(before.next(), Some((Token::Newline, _)))
+                || !matches!(before.next(), Some((Token::Colon, _)))
+            {
+                return false;
+            }
+            // The header starts after the end of the previous logical line
+            let header = before
+                .take_while(|w| !matches!(w.0, Token::Newline | Token::Indent | Token::Dedent))
+                .last();
+            matches!(header, Some((Token::Def | Token::Class | Token::Async, _)))
+        }
+        Some(_) => false,
+    };
+}
+
+fn is_single_line_break(text: &str) -> bool {
+    let trimmed = text.trim_matches([' ', '\t', '\x0C']);
+    return matches!(trimmed, "\n" | "\r" | "\r\n");
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    return text.split('\n').map(|w| w.trim_end_matches('\r')).collect();
+}
+
+fn detect_line_break(text: &str) -> &'static str {
+    return match text.find(['\n', '\r']) {
+        Some(idx) if text[idx..].starts_with("\r\n") => "\r\n",
+        Some(idx) if text[idx..].starts_with('\r') => "\r",
+        _ => "\n",
+    };
+}
+
+fn line_start(source: &str, offset: TextSize) -> TextSize {
+    return source[..offset.to_usize()]
+        .rfind(['\n', '\r'])
+        .map(|w| TextSize::new(w as u32 + 1))
+        .unwrap_or_default();
+}
+
+/// Display column of `offset`.
+fn column_at(source: &str, offset: TextSize, tab_width: usize) -> usize {
+    return display_width(
+        &source[TextRange::new(line_start(source, offset), offset)],
+        tab_width,
+    );
+}
+
+/// Number of columns `text` takes up, counting tabs as `tab_width` columns.
+fn display_width(text: &str, tab_width: usize) -> usize {
+    return text
+        .chars()
+        .map(|c| if c == '\t' { tab_width } else { 1 })
+        .sum();
 }
 
 #[cfg(test)]
 mod tests {
-    use super::to_char_index;
-
-    #[test]
-    fn test_to_char_offset() {
-        let text = "a𐐀b𐐀d";
-
-        // println!("{}", chars.to_string());
-        // println!("string length: {}", chars.to_string().len());
-        // println!("Chars length: {}", chars.chars().count());
-        //
-        // let mut self_count = 0;
-        // let mut utf_8_sum = 0;
-        // for c in chars.chars() {
-        //     self_count += 1;
-        //     utf_8_sum += c.len_utf8();
-        // }
-        //
-        // println!("{}", self_count);
-        // println!("utf_8_sum: {}", utf_8_sum);
-        let chars = text.chars();
-
-        let expected_char_offset = 2;
-        let actual_char_offset = to_char_index(chars.clone(), 3.into());
-        assert_eq!(expected_char_offset, actual_char_offset);
-
-        let expected_char_offset = 4;
-        let actual_char_offset = to_char_index(chars.clone(), 7.into());
-        assert_eq!(expected_char_offset, actual_char_offset);
+    use super::{CommentBlockKind, CommentWrapper, DocstringStyle, EndOfLine, WrapStrategy};
+
+    fn apply(source: &str, max_line_length: u64) -> String {
+        return apply_with(
+            source,
+            &CommentWrapper {
+                max_line_length,
+                ..Default::default()
+            },
+        );
+    }
+
+    fn apply_with(source: &str, wrapper: &CommentWrapper) -> String {
+        let mut result = source.to_string();
+        let mut edits = wrapper.process(source).unwrap();
+        edits.reverse();
+        for (range, new_text) in edits {
+            result.replace_range(range.start.to_usize()..range.end.to_usize(), &new_text);
+        }
+        return result;
+    }
+
+    #[test]
+    fn test_wrap_comment_block() {
+        let source = "x = 1\n    # one two three four five six\n    # seven\ny = 2\n";
+        let expected = "x = 1\n    # one two three\n    # four five six\n    # seven\ny = 2\n";
+        assert_eq!(apply(source, 21), expected);
+    }
+
+    #[test]
+    fn test_wrap_comment_block_keeps_paragraphs_and_pragmas() {
+        let source = "# aaa bbb ccc\n#\n# ddd\n# noqa: E501 is a long pragma\n";
+        let expected = "# aaa bbb\n# ccc\n#\n# ddd\n# noqa: E501 is a long pragma\n";
+        assert_eq!(apply(source, 10), expected);
+    }
+
+    #[test]
+    fn test_trailing_comment_is_untouched() {
+        let source = "x = 1  # a trailing comment that is far too long\n";
+        assert_eq!(apply(source, 20), source);
+    }
+
+    #[test]
+    fn test_wrap_bullets() {
+        let source = "# - aaa bbb ccc\n# - ddd\n";
+        let expected = "# - aaa bbb\n#   ccc\n# - ddd\n";
+        assert_eq!(apply(source, 12), expected);
+    }
+
+    #[test]
+    fn test_wrap_docstring() {
+        let source =
+            "def f():\n    \"\"\"One two three four.\n\n    Args:\n        x: value\n    \"\"\"\n";
+        let expected = "def f():\n    \"\"\"One two\n    three four.\n\n    Args:\n        x: value\n    \"\"\"\n";
+        assert_eq!(apply(source, 17), expected);
+    }
+
+    #[test]
+    fn test_wrap_single_line_docstring() {
+        let source = "\"\"\"One two three four.\"\"\"\n";
+        let expected = "\"\"\"One two\nthree four.\"\"\"\n";
+        assert_eq!(apply(source, 14), expected);
+    }
+
+    #[test]
+    fn test_wrap_docstring_starting_with_a_list_item() {
+        let source = "def f():\n    \"\"\"- aaa bbb ccc ddd eee fff\"\"\"\n";
+        let expected = "def f():\n    \"\"\"- aaa bbb ccc\n      ddd eee fff\"\"\"\n";
+        assert_eq!(apply(source, 20), expected);
+
+        let source = "def f():\n    \"\"\"1. aaa bbb ccc ddd eee fff\"\"\"\n";
+        let expected = "def f():\n    \"\"\"1. aaa bbb ccc\n       ddd eee fff\"\"\"\n";
+        assert_eq!(apply(source, 21), expected);
+    }
+
+    #[test]
+    fn test_only_docstrings_are_wrapped() {
+        let source = "\"\"\"One two three four.\"\"\"\n\n\n@decorator\nasync def f(\n    x,\n) -> int:\n    # Comment\n    \"\"\"One two three four.\"\"\"\n    foo(\n        \"\"\"SELECT a, b, c FROM table WHERE x = 1\"\"\"\n    )\n    \"\"\"One two three four.\"\"\"\n\n\nclass A:\n    \"\"\"One two three four.\"\"\"\n";
+        let wrapper = CommentWrapper::default();
+        let docstrings: Vec<usize> = wrapper
+            .comment_blocks(source)
+            .unwrap()
+            .iter()
+            .filter(|block| block.kind == CommentBlockKind::Docstring)
+            .map(|block| {
+                source[..usize::from(block.range.start())]
+                    .matches('\n')
+                    .count()
+            })
+            .collect();
+
+        assert_eq!(docstrings, vec![0, 8, 16]);
+        assert!(apply(source, 20)
+            .contains("\n        \"\"\"SELECT a, b, c FROM table WHERE x = 1\"\"\"\n"));
+    }
+
+    #[test]
+    fn test_unwrap_block() {
+        let source = "    # aaa\n    # bbb\n    #\n    # ccc\n";
+        let wrapper = CommentWrapper::default();
+        let blocks = wrapper.comment_blocks(source).unwrap();
+        assert_eq!(
+            wrapper.unwrap_block(source, &blocks[0]).unwrap(),
+            "# aaa bbb\n    #\n    # ccc"
+        );
+    }
+
+    #[test]
+    fn test_split_strategy_does_not_join_lines() {
+        let source = "# aaa bbb ccc\n# ddd\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 10,
+            strategy: WrapStrategy::Split,
+            ..Default::default()
+        };
+        assert_eq!(apply_with(source, &wrapper), "# aaa bbb\n# ccc\n# ddd\n");
+        assert_eq!(apply(source, 10), "# aaa bbb\n# ccc ddd\n");
+    }
+
+    #[test]
+    fn test_configured_pragma_prefixes() {
+        let source = "# NOSONAR aaa bbb ccc\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 10,
+            pragma_prefixes: vec!["NOSONAR".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(apply_with(source, &wrapper), source);
+    }
+
+    #[test]
+    fn test_google_docstring_fields() {
+        let source = "\"\"\"Summary.\n\nArgs:\n    x (int): aaa bbb ccc\n    y: ddd\n\"\"\"\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 20,
+            docstring_style: DocstringStyle::Google,
+            ..Default::default()
+        };
+        let expected =
+            "\"\"\"Summary.\n\nArgs:\n    x (int): aaa bbb\n        ccc\n    y: ddd\n\"\"\"\n";
+        assert_eq!(apply_with(source, &wrapper), expected);
+    }
+
+    #[test]
+    fn test_sphinx_docstring_fields() {
+        let source = "\"\"\"Summary.\n\n:param x: aaa bbb ccc\n:returns: ddd\n\"\"\"\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 20,
+            docstring_style: DocstringStyle::Sphinx,
+            ..Default::default()
+        };
+        let expected = "\"\"\"Summary.\n\n:param x: aaa bbb\n    ccc\n:returns: ddd\n\"\"\"\n";
+        assert_eq!(apply_with(source, &wrapper), expected);
+    }
+
+    #[test]
+    fn test_numpy_docstring_fields() {
+        let source =
+            "\"\"\"Summary.\n\nParameters\n----------\nx : int\n    aaa bbb ccc ddd\n\"\"\"\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 16,
+            docstring_style: DocstringStyle::Numpy,
+            ..Default::default()
+        };
+        let expected =
+            "\"\"\"Summary.\n\nParameters\n----------\nx : int\n    aaa bbb ccc\n    ddd\n\"\"\"\n";
+        assert_eq!(apply_with(source, &wrapper), expected);
+    }
+
+    #[test]
+    fn test_max_doc_length() {
+        let source = "# aaa bbb ccc\n";
+        let wrapper = CommentWrapper {
+            max_line_length: 20,
+            max_doc_length: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(apply_with(source, &wrapper), "# aaa bbb\n# ccc\n");
+    }
+
+    #[test]
+    fn test_new_line_breaks_follow_document() {
+        let source = "x = 1\r\n# aaa bbb ccc\r\n";
+        assert_eq!(apply(source, 10), "x = 1\r\n# aaa bbb\r\n# ccc\r\n");
+
+        let wrapper = CommentWrapper {
+            max_line_length: 10,
+            end_of_line: Some(EndOfLine::Cr),
+            ..Default::default()
+        };
+        assert_eq!(
+            apply_with(source, &wrapper),
+            "x = 1\r\n# aaa bbb\r# ccc\r\n"
+        );
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let source = "if x:\n\t# aaa bbb\n";
+        assert_eq!(apply(source, 12), "if x:\n\t# aaa\n\t# bbb\n");
+
+        let wrapper = CommentWrapper {
+            max_line_length: 12,
+            tab_width: 2,
+            ..Default::default()
+        };
+        assert_eq!(apply_with(source, &wrapper), source);
     }
 }
@@ -0,0 +1,40 @@
+use crate::lsp::{
+    comment_wrapper::{Position, Range, TextEdit},
+    lexer::{text_range::TextRange, text_size::TextSize},
+    line_index::{LineIndex, PositionEncoding},
+};
+
+/// An open text document together with the index to convert its positions.
+pub struct Document {
+    pub text: String,
+    line_index: LineIndex,
+}
+
+impl Document {
+    pub fn new(text: String, encoding: PositionEncoding) -> Document {
+        let line_index = LineIndex::new(&text, encoding);
+        return Document { text, line_index };
+    }
+
+    pub fn offset(&self, position: Position) -> TextSize {
+        return self.line_index.offset(&self.text, position);
+    }
+
+    pub fn range(&self, text_range: TextRange) -> Range {
+        return self.line_index.range(&self.text, text_range);
+    }
+
+    pub fn text_edit(&self, text_range: TextRange, new_text: String) -> TextEdit {
+        return TextEdit {
+            range: self.range(text_range),
+            new_text,
+        };
+    }
+
+    pub fn text_edits(&self, edits: Vec<(TextRange, String)>) -> Vec<TextEdit> {
+        return edits
+            .into_iter()
+            .map(|(text_range, new_text)| self.text_edit(text_range, new_text))
+            .collect();
+    }
+}
@@ -0,0 +1,170 @@
+use regex::Regex;
+use std::cmp::Ordering;
+
+use crate::lsp::{
+    comment_wrapper::{CommentBlockKind, CommentWrapper},
+    lexer::{
+        lex::{Lexer, LexicalError},
+        text_range::TextRange,
+        text_size::TextSize,
+        token::Token,
+    },
+};
+
+const URL_PATTERN: &str = r#"https?://[^\s<>"'`]+"#;
+const URL_TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}'];
+
+/// Turns matches of `pattern` into links to `url_template`, where capture groups can be referred
+/// to as `$1` or `${name}`.
+#[derive(Clone)]
+pub struct TicketLink {
+    pub pattern: Regex,
+    pub url_template: String,
+}
+
+#[derive(Clone)]
+pub struct DocumentLinker {
+    url_pattern: Regex,
+    ticket_links: Vec<TicketLink>,
+}
+
+impl DocumentLinker {
+    pub fn new(ticket_links: Vec<TicketLink>) -> DocumentLinker {
+        return DocumentLinker {
+            url_pattern: Regex::new(URL_PATTERN).expect("URL pattern is valid"),
+            ticket_links,
+        };
+    }
+
+    /// Finds the links in the comments and docstrings of `source`, together with their targets.
+    /// Docstrings are told apart from other strings as `comment_wrapper` does.
+    pub fn find_links(
+        &self,
+        source: &str,
+        comment_wrapper: &CommentWrapper,
+    ) -> Result<Vec<(TextRange, String)>, LexicalError> {
+        let mut text_ranges: Vec<TextRange> = comment_wrapper
+            .comment_blocks(source)?
+            .into_iter()
+            .filter(|block| block.kind == CommentBlockKind::Docstring)
+            .map(|block| block.range)
+            .collect();
+        // Trailing comments are not part of any block, but are searched as well
+        for token in Lexer::new(source.chars()) {
+            if let (Token::Comment(_), text_range) = token? {
+                text_ranges.push(text_range);
+            }
+        }
+        text_ranges.sort_by_key(|text_range| text_range.start);
+
+        return Ok(text_ranges
+            .into_iter()
+            .flat_map(|text_range| self.find_links_in(source, text_range))
+            .collect());
+    }
+
+    fn find_links_in(&self, source: &str, text_range: TextRange) -> Vec<(TextRange, String)> {
+        let text = &source[text_range];
+        let to_text_range = |start: usize, end: usize| {
+            TextRange::new(
+                text_range.start + TextSize::new(start as u32),
+                text_range.start + TextSize::new(end as u32),
+            )
+        };
+
+        let mut links: Vec<(TextRange, String)> = self
+            .url_pattern
+            .find_iter(text)
+            .map(|w| {
+                let url = w.as_str().trim_end_matches(URL_TRAILING_PUNCTUATION);
+                (
+                    to_text_range(w.start(), w.start() + url.len()),
+                    url.to_string(),
+                )
+            })
+            .collect();
+
+        for ticket_link in self.ticket_links.iter() {
+            for captures in ticket_link.pattern.captures_iter(text) {
+                let ticket = captures.get(0).expect("Group 0 is always the whole match");
+                let range = to_text_range(ticket.start(), ticket.end());
+
+                // Tickets that are part of an url are already covered by that url
+                if links
+                    .iter()
+                    .any(|(link_range, _)| link_range.ordering(range) == Ordering::Equal)
+                {
+                    continue;
+                }
+
+                let mut target = String::new();
+                captures.expand(&ticket_link.url_template, &mut target);
+                links.push((range, target));
+            }
+        }
+
+        links.sort_by_key(|(range, _)| range.start);
+        return links;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DocumentLinker, TicketLink};
+    use crate::lsp::comment_wrapper::CommentWrapper;
+    use regex::Regex;
+
+    #[test]
+    fn test_find_links() {
+        let source = "x = 1  # See https://example.com/a_b?c=1.\ndef f():\n    \"\"\"Fixes GH-12 and JIRA-5\"\"\"\ny = \"GH-3\"\nquery = \"\"\"SELECT 'GH-4', 'https://example.org'\"\"\"\nrun(\n    \"\"\"SELECT 'GH-5'\"\"\"\n)\n";
+        let document_linker = DocumentLinker::new(vec![
+            TicketLink {
+                pattern: Regex::new(r"GH-(\d+)").unwrap(),
+                url_template: "https://github.com/org/repo/issues/$1".to_string(),
+            },
+            TicketLink {
+                pattern: Regex::new(r"(?P<ticket>JIRA-\d+)").unwrap(),
+                url_template: "https://jira.example.com/browse/${ticket}".to_string(),
+            },
+        ]);
+
+        let links: Vec<(&str, String)> = document_linker
+            .find_links(source, &CommentWrapper::default())
+            .unwrap()
+            .into_iter()
+            .map(|(range, target)| (&source[range], target))
+            .collect();
+
+        assert_eq!(
+            links,
+            vec![
+                (
+                    "https://example.com/a_b?c=1",
+                    "https://example.com/a_b?c=1".to_string()
+                ),
+                ("GH-12", "https://github.com/org/repo/issues/12".to_string()),
+                (
+                    "JIRA-5",
+                    "https://jira.example.com/browse/JIRA-5".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ticket_inside_url() {
+        let source = "# https://example.com/GH-12\n";
+        let document_linker = DocumentLinker::new(vec![TicketLink {
+            pattern: Regex::new(r"GH-(\d+)").unwrap(),
+            url_template: "https://github.com/org/repo/issues/$1".to_string(),
+        }]);
+
+        assert_eq!(
+            document_linker
+                .find_links(source, &CommentWrapper::default())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -172,12 +172,14 @@ where
     type Item = char;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // The cursor points at the first char of the window, so advance it by the char that
+        // is leaving the window rather than the one entering it.
+        if let Some(c) = self.window[0] {
+            self.cursor += TextSize::from(c)
+        }
         self.window.rotate_left(1);
         let next = self.source.next();
         *self.window.last_mut().expect("Will always be populated") = next;
-        if let Some(c) = next {
-            self.cursor += TextSize::from(c)
-        }
         return self.window[0];
     }
 }
@@ -1581,4 +1583,12 @@ mod tests {
         test_triple_quoted_mac_eol: MAC_EOL,
         test_triple_quoted_unix_eol: UNIX_EOL,
     }
+
+    #[test]
+    fn test_text_ranges() {
+        let source = "é = 1  # ü";
+        let lexer = Lexer::new(source.chars());
+        let ranges: Vec<&str> = lexer.map(|x| &source[x.unwrap().1]).collect();
+        assert_eq!(ranges, vec!["é", "=", "1", "# ü", ""]);
+    }
 }
@@ -77,8 +77,8 @@ impl TextRange {
     }
 
     pub fn cover(self, other: TextRange) -> TextRange {
-        let lo = cmp::max(self.start(), other.start());
-        let hi = cmp::min(self.end(), other.end());
+        let lo = cmp::min(self.start(), other.start());
+        let hi = cmp::max(self.end(), other.end());
 
         return TextRange::new(lo, hi);
     }
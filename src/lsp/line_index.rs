@@ -0,0 +1,170 @@
+use serde::Serialize;
+
+use crate::lsp::{
+    comment_wrapper::{Position, Range},
+    lexer::{text_range::TextRange, text_size::TextSize},
+};
+
+/// The unit in which the `character` of a LSP position is counted.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[default]
+    #[serde(rename = "utf-16")]
+    Utf16,
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first encoding the client prefers that is supported, falling back to utf-16
+    /// which every client has to support.
+    pub fn negotiate(position_encodings: &[String]) -> PositionEncoding {
+        return position_encodings
+            .iter()
+            .find_map(|encoding| match encoding.as_str() {
+                "utf-8" => Some(PositionEncoding::Utf8),
+                "utf-16" => Some(PositionEncoding::Utf16),
+                "utf-32" => Some(PositionEncoding::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default();
+    }
+
+    fn len(&self, c: char) -> u32 {
+        return match self {
+            PositionEncoding::Utf8 => c.len_utf8() as u32,
+            PositionEncoding::Utf16 => c.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        };
+    }
+}
+
+/// Converts between the utf-8 offsets of the lexer and LSP positions of a text.
+pub struct LineIndex {
+    line_starts: Vec<TextSize>,
+    encoding: PositionEncoding,
+}
+
+impl LineIndex {
+    pub fn new(text: &str, encoding: PositionEncoding) -> LineIndex {
+        let mut line_starts = vec![TextSize::new(0)];
+        let bytes = text.as_bytes();
+
+        for (idx, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' => line_starts.push(TextSize::new(idx as u32 + 1)),
+                b'\r' if bytes.get(idx + 1) != Some(&b'\n') => {
+                    line_starts.push(TextSize::new(idx as u32 + 1))
+                }
+                _ => {}
+            }
+        }
+
+        return LineIndex {
+            line_starts,
+            encoding,
+        };
+    }
+
+    pub fn position(&self, text: &str, offset: TextSize) -> Position {
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let line_start = self.line_starts[line];
+
+        return Position {
+            line: line as u32,
+            character: text[TextRange::new(line_start, offset)]
+                .chars()
+                .map(|c| self.encoding.len(c))
+                .sum(),
+        };
+    }
+
+    /// Converts `position` into an offset, clamping positions past the end of a line to its end.
+    pub fn offset(&self, text: &str, position: Position) -> TextSize {
+        let Some(line_start) = self.line_starts.get(position.line as usize) else {
+            return TextSize::new(text.len() as u32);
+        };
+
+        let mut offset = *line_start;
+        let mut character: u32 = 0;
+        for c in text[line_start.to_usize()..].chars() {
+            if character >= position.character || matches!(c, '\n' | '\r') {
+                break;
+            }
+            character += self.encoding.len(c);
+            offset += TextSize::from(c);
+        }
+
+        return offset;
+    }
+
+    pub fn range(&self, text: &str, text_range: TextRange) -> Range {
+        return Range {
+            start: self.position(text, text_range.start),
+            end: self.position(text, text_range.end),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineIndex, PositionEncoding};
+    use crate::lsp::comment_wrapper::Position;
+
+    #[test]
+    fn test_position_conversion() {
+        let text = "a\r\nb𐐀c\rd\né";
+        let cases = [
+            (PositionEncoding::Utf8, 5),
+            (PositionEncoding::Utf16, 3),
+            (PositionEncoding::Utf32, 2),
+        ];
+
+        for (encoding, character) in cases {
+            let line_index = LineIndex::new(text, encoding);
+            let position = Position { line: 1, character };
+            assert_eq!(line_index.position(text, 8.into()), position);
+            assert_eq!(line_index.offset(text, position).to_u32(), 8);
+        }
+    }
+
+    #[test]
+    fn test_offset_is_clamped_to_line_end() {
+        let text = "ab\ncd";
+        let line_index = LineIndex::new(text, PositionEncoding::Utf16);
+
+        let offset = line_index.offset(
+            text,
+            Position {
+                line: 0,
+                character: 10,
+            },
+        );
+        assert_eq!(offset.to_u32(), 2);
+
+        let offset = line_index.offset(
+            text,
+            Position {
+                line: 5,
+                character: 0,
+            },
+        );
+        assert_eq!(offset.to_u32(), 5);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let encodings = vec!["utf-32".to_string(), "utf-8".to_string()];
+        assert_eq!(
+            PositionEncoding::negotiate(&encodings),
+            PositionEncoding::Utf32
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(&["latin-1".to_string()]),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(PositionEncoding::negotiate(&[]), PositionEncoding::Utf16);
+    }
+}
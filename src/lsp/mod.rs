@@ -1,6 +1,11 @@
 pub mod comment_wrapper;
+pub mod document;
+pub mod document_link;
 pub mod lexer;
+pub mod line_index;
 pub mod notification;
 pub mod request;
 pub mod request_handling;
 pub mod response;
+pub mod server_request;
+pub mod uri;
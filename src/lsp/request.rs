@@ -1,29 +1,111 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::comment_wrapper::Position;
+
+/// The id of a request of the client, which is sent back unchanged in its response.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RequestId::Number(id) => write!(f, "{}", id),
+            RequestId::String(id) => write!(f, "{:?}", id),
+        };
+    }
+}
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum IncommingMessage<'a> {
     #[serde(rename_all = "camelCase")]
     Request {
-        id: u32,
+        id: RequestId,
+        method: String,
+        params: Option<Params>,
+        jsonrpc: &'a str,
+    },
+    #[serde(rename_all = "camelCase")]
+    Notification {
         method: String,
         params: Option<Params>,
         jsonrpc: &'a str,
     },
+    // Response of the client to a request that was sent by the server
     #[serde(rename_all = "camelCase")]
-    Notification { method: String, jsonrpc: &'a str },
+    Response {
+        id: Option<u32>,
+        result: Option<serde_json::Value>,
+        error: Option<serde_json::Value>,
+        jsonrpc: &'a str,
+    },
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum Params {
+    #[serde(rename_all = "camelCase")]
+    ExecuteCommandParams {
+        command: String,
+        arguments: Option<Vec<serde_json::Value>>,
+    },
+    #[serde(rename_all = "camelCase")]
+    DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier,
+        content_changes: Vec<TextDocumentContentChangeEvent>,
+    },
     #[serde(rename_all = "camelCase")]
     DocumentFormattingParams {
         text_document: TextDocumentIdentifier,
         options: FormattingOptions,
     },
     #[serde(rename_all = "camelCase")]
-    InitializeParams { client_info: ClientInfo },
+    HoverParams {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    #[serde(rename_all = "camelCase")]
+    SetTraceParams {
+        value: TraceValue,
+    },
+    #[serde(rename_all = "camelCase")]
+    CancelParams {
+        id: RequestId,
+    },
+    #[serde(rename_all = "camelCase")]
+    DidChangeWatchedFilesParams {
+        changes: Vec<FileEvent>,
+    },
+    #[serde(rename_all = "camelCase")]
+    DidChangeWorkspaceFoldersParams {
+        event: WorkspaceFoldersChangeEvent,
+    },
+    #[serde(rename_all = "camelCase")]
+    DidChangeConfigurationParams {
+        settings: serde_json::Value,
+    },
+    #[serde(rename_all = "camelCase")]
+    WillSaveTextDocumentParams {
+        text_document: TextDocumentIdentifier,
+        reason: u8,
+    },
+    #[serde(rename_all = "camelCase")]
+    DidOpenTextDocumentParams {
+        text_document: TextDocumentItem,
+    },
+    InitializeParams(InitializeParams),
+    // Keep second to last, as every other text document request also matches this shape
+    #[serde(rename_all = "camelCase")]
+    TextDocumentParams {
+        text_document: TextDocumentIdentifier,
+    },
+    // Params of unknown requests, or params that do not match their method
+    Unknown(serde_json::Value),
 }
 
 #[derive(Deserialize, Debug)]
@@ -32,6 +114,38 @@ pub struct TextDocumentIdentifier {
     pub uri: String,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionedTextDocumentIdentifier {
+    pub uri: String,
+    pub version: i32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentItem {
+    pub uri: String,
+    pub language_id: String,
+    pub version: i32,
+    pub text: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentContentChangeEvent {
+    // Only full document syncs are supported, so the change never has a range
+    pub text: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEvent {
+    pub uri: String,
+    /// Created (1), changed (2) or deleted (3).
+    #[serde(rename = "type")]
+    pub change_type: u8,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct FormattingOptions {
@@ -41,9 +155,109 @@ pub struct FormattingOptions {
     // insert_final_newline: Option<bool>,
     // trim_final_newlines: Option<bool>,
 }
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeParams {
+    pub process_id: Option<u32>,
+    pub client_info: Option<ClientInfo>,
+    pub root_uri: Option<String>,
+    pub capabilities: ClientCapabilities,
+    /// Parsed leniently, so that a mistyped option does not fail the whole request.
+    pub initialization_options: Option<serde_json::Value>,
+    #[serde(default)]
+    pub trace: TraceValue,
+    pub workspace_folders: Option<Vec<WorkspaceFolder>>,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ClientInfo {
     pub name: String,
-    pub version: String,
+    pub version: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFolder {
+    pub uri: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFoldersChangeEvent {
+    pub added: Vec<WorkspaceFolder>,
+    pub removed: Vec<WorkspaceFolder>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceValue {
+    #[default]
+    Off,
+    Messages,
+    Verbose,
+}
+
+/// The capabilities of the client that the server makes use of. Everything the client leaves out
+/// is assumed to be unsupported.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClientCapabilities {
+    pub workspace: WorkspaceClientCapabilities,
+    pub text_document: TextDocumentClientCapabilities,
+    pub general: GeneralClientCapabilities,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkspaceClientCapabilities {
+    pub apply_edit: bool,
+    pub workspace_folders: bool,
+    pub configuration: bool,
+    pub did_change_watched_files: DidChangeWatchedFilesClientCapabilities,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DidChangeWatchedFilesClientCapabilities {
+    pub dynamic_registration: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TextDocumentClientCapabilities {
+    pub synchronization: TextDocumentSyncClientCapabilities,
+    pub hover: Option<HoverClientCapabilities>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TextDocumentSyncClientCapabilities {
+    pub will_save_wait_until: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HoverClientCapabilities {
+    pub content_format: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GeneralClientCapabilities {
+    pub position_encodings: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InitializationOptions {
+    pub ticket_links: Vec<TicketLinkOption>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TicketLinkOption {
+    pub pattern: String,
+    pub url: String,
 }
@@ -1,58 +1,447 @@
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
 use crate::lsp::lexer::lex;
 use crate::{
-    constants,
+    config::{self, ConfigCache, Settings},
+    constants, format,
     lsp::response::{Response, Result as ResponseResult, ServerCapabilities, ServerInfo},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use super::{
-    request::{ClientInfo, FormattingOptions, IncommingMessage, Params, TextDocumentIdentifier},
-    response::{ResponseError, ResponseErrorCode},
+    comment_wrapper::{CommentWrapper, Position, TextEdit},
+    document::Document,
+    document_link::{DocumentLinker, TicketLink},
+    line_index::PositionEncoding,
+    request::{
+        ClientCapabilities, FileEvent, FormattingOptions, IncommingMessage, InitializationOptions,
+        InitializeParams, Params, RequestId, TextDocumentContentChangeEvent,
+        TextDocumentIdentifier, TextDocumentItem, TraceValue, VersionedTextDocumentIdentifier,
+        WorkspaceFolder, WorkspaceFoldersChangeEvent,
+    },
+    response::{
+        DocumentLink, DocumentLinkOptions, ExecuteCommandOptions, FoldingRange, MarkupContent,
+        ResponseError, ResponseErrorCode, ResponseErrorData, TextDocumentSyncKind,
+        TextDocumentSyncOptions, WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
+    },
+    server_request::{
+        ApplyWorkspaceEditResult, ConfigurationItem, MessageActionItem, Registration,
+        ServerRequest, ServerRequestParams, WorkspaceEdit,
+    },
+    uri,
 };
 
+/// Cloning the handler is cheap, so that read-only requests can be answered from a snapshot of it
+/// on another thread while the original keeps handling notifications.
+#[derive(Clone)]
 pub struct RequestHandler {
-    is_active: bool,
+    state: ServerState,
+    documents: HashMap<String, Arc<Document>>,
+    position_encoding: PositionEncoding,
+    /// The wrapper for documents that no project configures.
+    comment_wrapper: CommentWrapper,
+    config_cache: Arc<ConfigCache>,
+    /// Settings of the editor, which take precedence over the settings of projects.
+    editor_settings: Settings,
+    /// Settings of the editor for single documents, by uri, as pulled from the client.
+    document_editor_settings: HashMap<String, Settings>,
+    document_linker: DocumentLinker,
+    next_server_request_id: Arc<AtomicU32>,
+    /// Params of the requests sent to the client that have not been answered yet, by id.
+    server_requests: Arc<Mutex<HashMap<u32, ServerRequestParams>>>,
+    client_capabilities: ClientCapabilities,
+    root_uri: Option<String>,
+    workspace_folders: Vec<WorkspaceFolder>,
+    trace: TraceValue,
+    pending_requests: Arc<Mutex<PendingRequests>>,
+}
+
+/// Requests that have been received but not handled yet, and which of them the client cancelled.
+#[derive(Default)]
+struct PendingRequests {
+    received: HashSet<RequestId>,
+    cancelled: HashSet<RequestId>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ServerState {
+    /// Waiting for the `initialize` request.
+    Uninitialized,
+    Initialized,
+    /// The `shutdown` request has been received, so only `exit` is expected.
+    ShutDown,
 }
 
 pub enum RequestHandlerAction<'a> {
     ResponseAction(Response<'a>),
-    ExitAction,
+    ResponseWithRequestsAction(Response<'a>, Vec<ServerRequest<'a>>),
+    RequestsAction(Vec<ServerRequest<'a>>),
+    ExitAction(i32),
     NoopAction,
 }
 
+/// Requests the handler knows about, so that malformed params can be told apart from
+/// unsupported methods.
+const HANDLED_METHODS: &[&str] = &[
+    "initialize",
+    "shutdown",
+    "textDocument/formatting",
+    "textDocument/willSaveWaitUntil",
+    "textDocument/hover",
+    "textDocument/foldingRange",
+    "textDocument/documentLink",
+    "workspace/executeCommand",
+];
+
+/// Requests that change the state of the server, so they are handled even when cancelled.
+const UNCANCELLABLE_METHODS: &[&str] = &["initialize", "shutdown"];
+
+/// Requests that do not change the state of the server, so they can be answered from a snapshot.
+const READ_ONLY_METHODS: &[&str] = &[
+    "textDocument/formatting",
+    "textDocument/willSaveWaitUntil",
+    "textDocument/hover",
+    "textDocument/foldingRange",
+    "textDocument/documentLink",
+    "workspace/executeCommand",
+];
+
+#[derive(Debug)]
+pub enum RequestHandlerError {
+    /// The message is not a valid JSON-RPC message.
+    ParseError(String),
+    /// The server does not implement the requested method.
+    MethodNotFound { id: RequestId, method: String },
+    /// The params of the request do not match the requested method.
+    InvalidParams { id: RequestId, method: String },
+    /// A request was received before the `initialize` request.
+    ServerNotInitialized { id: RequestId },
+    /// The request is not valid in the current state of the server.
+    InvalidRequest { id: RequestId, reason: &'static str },
+    /// The client cancelled the request before it was handled.
+    RequestCancelled { id: RequestId },
+}
+
+impl RequestHandlerError {
+    /// The error response that should be sent to the client.
+    pub fn to_response(&self) -> Response<'_> {
+        let (id, code, message, data) = match self {
+            RequestHandlerError::ParseError(reason) => (
+                None,
+                ResponseErrorCode::ParseError,
+                "Could not parse message",
+                Some(reason.as_str()),
+            ),
+            RequestHandlerError::MethodNotFound { id, method } => (
+                Some(id.clone()),
+                ResponseErrorCode::MethodNotFound,
+                "Method not found",
+                Some(method.as_str()),
+            ),
+            RequestHandlerError::InvalidParams { id, method } => (
+                Some(id.clone()),
+                ResponseErrorCode::InvalidParams,
+                "Invalid params",
+                Some(method.as_str()),
+            ),
+            RequestHandlerError::ServerNotInitialized { id } => (
+                Some(id.clone()),
+                ResponseErrorCode::ServerNotInitialized,
+                "Server has not been initialized",
+                None,
+            ),
+            RequestHandlerError::InvalidRequest { id, reason } => (
+                Some(id.clone()),
+                ResponseErrorCode::InvalidRequest,
+                *reason,
+                None,
+            ),
+            RequestHandlerError::RequestCancelled { id } => (
+                Some(id.clone()),
+                ResponseErrorCode::RequestCancelled,
+                "Request has been cancelled",
+                None,
+            ),
+        };
+
+        return Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id,
+            result: None,
+            error: Some(ResponseError {
+                code,
+                message,
+                data: data.map(ResponseErrorData::String),
+            }),
+        };
+    }
+}
+
+impl fmt::Display for RequestHandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RequestHandlerError::ParseError(reason) => {
+                write!(f, "Could not parse message: {}", reason)
+            }
+            RequestHandlerError::MethodNotFound { id, method } => {
+                write!(f, "Method not found for request {}: {}", id, method)
+            }
+            RequestHandlerError::InvalidParams { id, method } => {
+                write!(f, "Invalid params for request {}: {}", id, method)
+            }
+            RequestHandlerError::ServerNotInitialized { id } => {
+                write!(f, "Request {} received before initialize", id)
+            }
+            RequestHandlerError::InvalidRequest { id, reason } => {
+                write!(f, "Invalid request {}: {}", id, reason)
+            }
+            RequestHandlerError::RequestCancelled { id } => {
+                write!(f, "Request {} has been cancelled", id)
+            }
+        };
+    }
+}
+
 impl RequestHandler {
     pub fn new() -> RequestHandler {
-        return RequestHandler { is_active: true };
+        return RequestHandler {
+            state: ServerState::Uninitialized,
+            documents: HashMap::new(),
+            position_encoding: PositionEncoding::Utf16,
+            comment_wrapper: CommentWrapper::default(),
+            config_cache: Arc::new(ConfigCache::new()),
+            editor_settings: Settings::default(),
+            document_editor_settings: HashMap::new(),
+            document_linker: DocumentLinker::new(vec![]),
+            next_server_request_id: Arc::new(AtomicU32::new(0)),
+            server_requests: Arc::new(Mutex::new(HashMap::new())),
+            client_capabilities: ClientCapabilities::default(),
+            root_uri: None,
+            workspace_folders: vec![],
+            trace: TraceValue::Off,
+            pending_requests: Arc::new(Mutex::new(PendingRequests::default())),
+        };
+    }
+
+    /// Whether `message` can be handled by `handle_read_only_request` on a snapshot.
+    pub fn is_read_only(message: &IncommingMessage) -> bool {
+        return match message {
+            IncommingMessage::Request { method, .. } => {
+                READ_ONLY_METHODS.contains(&method.as_str())
+            }
+            _ => false,
+        };
+    }
+
+    /// Inspects a message as soon as it is received, before the messages ahead of it have been
+    /// handled, so that requests can be cancelled before their turn comes.
+    pub fn look_ahead(&self, message: &IncommingMessage) {
+        match message {
+            IncommingMessage::Request { id, .. } => {
+                self.pending_requests().received.insert(id.clone());
+            }
+            IncommingMessage::Notification {
+                method,
+                params: Some(Params::CancelParams { id }),
+                ..
+            } if method == "$/cancelRequest" => self.handle_cancel_request_notification(id.clone()),
+            _ => {}
+        }
+    }
+
+    fn pending_requests(&self) -> MutexGuard<'_, PendingRequests> {
+        return self
+            .pending_requests
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+    }
+
+    /// Marks the request as handled, and returns whether it was cancelled in the meantime.
+    fn take_cancelled(&self, id: &RequestId) -> bool {
+        let mut pending_requests = self.pending_requests();
+        pending_requests.received.remove(id);
+        return pending_requests.cancelled.remove(id);
     }
 
     pub fn handle_request<'a>(
         &'a mut self,
         request: &'a IncommingMessage<'a>,
-    ) -> Result<RequestHandlerAction, String> {
+    ) -> Result<RequestHandlerAction, RequestHandlerError> {
         return match request {
-            IncommingMessage::Request { id, .. } if !self.is_active => {
-                Ok(RequestHandlerAction::ResponseAction(Response {
-                    id: Some(*id),
-                    result: None,
-                    jsonrpc: constants::JSON_RPC_VERSION,
-                    error: Some(ResponseError {
-                        code: ResponseErrorCode::InvalidRequest,
-                        data: None,
-                        message: "Server has been shut down, so new requests are invalid.",
-                    }),
-                }))
+            IncommingMessage::Notification { method, .. } if method == "exit" => Ok(
+                RequestHandlerAction::ExitAction(self.handle_exit_notification()),
+            ),
+            _ if RequestHandler::is_read_only(request) => self.handle_read_only_request(request),
+            IncommingMessage::Request { id, method, .. }
+                if self.take_cancelled(id) && !UNCANCELLABLE_METHODS.contains(&method.as_str()) =>
+            {
+                Err(RequestHandlerError::RequestCancelled { id: id.clone() })
+            }
+            IncommingMessage::Request { id, method, .. }
+                if method == "initialize" && self.state != ServerState::Uninitialized =>
+            {
+                Err(RequestHandlerError::InvalidRequest {
+                    id: id.clone(),
+                    reason: "Server has already been initialized",
+                })
+            }
+            IncommingMessage::Request { id, method, .. }
+                if method != "initialize" && self.state == ServerState::Uninitialized =>
+            {
+                Err(RequestHandlerError::ServerNotInitialized { id: id.clone() })
+            }
+            IncommingMessage::Notification { method, .. }
+                if self.state == ServerState::Uninitialized =>
+            {
+                debug!(
+                    "Dropping notification {} received before initialize",
+                    method
+                );
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Request { id, .. } if self.state == ServerState::ShutDown => {
+                Err(RequestHandlerError::InvalidRequest {
+                    id: id.clone(),
+                    reason: "Server has been shut down, so new requests are invalid",
+                })
             }
             IncommingMessage::Request {
                 ref method,
-                params: Some(Params::InitializeParams { client_info, .. }),
+                params: Some(Params::InitializeParams(initialize_params)),
                 id,
                 ..
             } if method == "initialize" => Ok(RequestHandlerAction::ResponseAction(
-                self.handle_initialize_request(*id, client_info),
+                self.handle_initialize_request(id.clone(), initialize_params),
             )),
             IncommingMessage::Request { ref method, id, .. } if method == "shutdown" => Ok(
-                RequestHandlerAction::ResponseAction(self.handle_shutdown_request(*id)),
+                RequestHandlerAction::ResponseAction(self.handle_shutdown_request(id.clone())),
             ),
+            IncommingMessage::Response {
+                id, result, error, ..
+            } => {
+                self.handle_response(*id, result.as_ref(), error.as_ref());
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::DidOpenTextDocumentParams { text_document }),
+                ..
+            } if method == "textDocument/didOpen" => {
+                self.handle_textdocument_did_open_notification(text_document);
+                Ok(self.pull_configuration(vec![text_document.uri.clone()]))
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::DidChangeConfigurationParams { settings }),
+                ..
+            } if method == "workspace/didChangeConfiguration" => {
+                Ok(self.handle_workspace_did_change_configuration_notification(settings))
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::DidChangeWorkspaceFoldersParams { event }),
+                ..
+            } if method == "workspace/didChangeWorkspaceFolders" => {
+                Ok(self.handle_workspace_did_change_workspace_folders_notification(event))
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params:
+                    Some(Params::DidChangeTextDocumentParams {
+                        text_document,
+                        content_changes,
+                    }),
+                ..
+            } if method == "textDocument/didChange" => {
+                self.handle_textdocument_did_change_notification(text_document, content_changes);
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::TextDocumentParams { text_document }),
+                ..
+            } if method == "textDocument/didClose" => {
+                self.handle_textdocument_did_close_notification(text_document);
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::SetTraceParams { value }),
+                ..
+            } if method == "$/setTrace" => {
+                debug!("Setting trace to {:?}", value);
+                self.trace = *value;
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::CancelParams { id }),
+                ..
+            } if method == "$/cancelRequest" => {
+                self.handle_cancel_request_notification(id.clone());
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification {
+                ref method,
+                params: Some(Params::DidChangeWatchedFilesParams { changes }),
+                ..
+            } if method == "workspace/didChangeWatchedFiles" => {
+                self.handle_workspace_did_change_watched_files_notification(changes);
+                Ok(RequestHandlerAction::NoopAction)
+            }
+            IncommingMessage::Notification { ref method, .. } if method == "initialized" => {
+                Ok(self.handle_initialized_notification())
+            }
+            IncommingMessage::Request { id, method, .. }
+                if HANDLED_METHODS.contains(&method.as_str()) =>
+            {
+                Err(RequestHandlerError::InvalidParams {
+                    id: id.clone(),
+                    method: method.clone(),
+                })
+            }
+            IncommingMessage::Request { id, method, .. } => {
+                Err(RequestHandlerError::MethodNotFound {
+                    id: id.clone(),
+                    method: method.clone(),
+                })
+            }
+            IncommingMessage::Notification { method, .. } => {
+                debug!("Ignoring notification {}", method);
+                Ok(RequestHandlerAction::NoopAction)
+            }
+        };
+    }
+
+    /// Handles the requests that only read the state of the server, which is why they can be
+    /// handled on a snapshot of the handler.
+    pub fn handle_read_only_request<'a>(
+        &'a self,
+        request: &'a IncommingMessage<'a>,
+    ) -> Result<RequestHandlerAction<'a>, RequestHandlerError> {
+        return match request {
+            IncommingMessage::Request { id, .. } if self.take_cancelled(id) => {
+                Err(RequestHandlerError::RequestCancelled { id: id.clone() })
+            }
+            IncommingMessage::Request { id, .. } if self.state == ServerState::Uninitialized => {
+                Err(RequestHandlerError::ServerNotInitialized { id: id.clone() })
+            }
+            IncommingMessage::Request { id, .. } if self.state == ServerState::ShutDown => {
+                Err(RequestHandlerError::InvalidRequest {
+                    id: id.clone(),
+                    reason: "Server has been shut down, so new requests are invalid",
+                })
+            }
             IncommingMessage::Request {
                 id,
                 ref method,
@@ -63,29 +452,158 @@ impl RequestHandler {
                     }),
                 ..
             } if method == "textDocument/formatting" => Ok(RequestHandlerAction::ResponseAction(
-                self.handle_textdocument_formatting_request(*id, text_document, options),
+                self.handle_textdocument_formatting_request(id.clone(), text_document, options),
             )),
-            IncommingMessage::Notification { ref method, .. } if method == "initialized" => {
-                Ok(RequestHandlerAction::NoopAction)
+            IncommingMessage::Request {
+                id,
+                ref method,
+                params:
+                    Some(Params::HoverParams {
+                        text_document,
+                        position,
+                    }),
+                ..
+            } if method == "textDocument/hover" => Ok(RequestHandlerAction::ResponseAction(
+                self.handle_textdocument_hover_request(id.clone(), text_document, position),
+            )),
+            IncommingMessage::Request {
+                id,
+                ref method,
+                params: Some(Params::TextDocumentParams { text_document }),
+                ..
+            } if method == "textDocument/foldingRange" => Ok(RequestHandlerAction::ResponseAction(
+                self.handle_textdocument_folding_range_request(id.clone(), text_document),
+            )),
+            IncommingMessage::Request {
+                id,
+                ref method,
+                params: Some(Params::TextDocumentParams { text_document }),
+                ..
+            } if method == "textDocument/documentLink" => Ok(RequestHandlerAction::ResponseAction(
+                self.handle_textdocument_document_link_request(id.clone(), text_document),
+            )),
+            IncommingMessage::Request {
+                id,
+                ref method,
+                params: Some(Params::WillSaveTextDocumentParams { text_document, .. }),
+                ..
+            } if method == "textDocument/willSaveWaitUntil" => {
+                Ok(RequestHandlerAction::ResponseAction(
+                    self.handle_textdocument_will_save_wait_until_request(
+                        id.clone(),
+                        text_document,
+                    ),
+                ))
             }
-            IncommingMessage::Notification { method, .. } if method == "exit" => {
-                Ok(RequestHandlerAction::ExitAction)
+            IncommingMessage::Request {
+                id,
+                ref method,
+                params: Some(Params::ExecuteCommandParams { command, arguments }),
+                ..
+            } if method == "workspace/executeCommand" => Ok(self
+                .handle_workspace_execute_command_request(
+                    id.clone(),
+                    command,
+                    arguments.as_deref().unwrap_or(&[]),
+                )),
+            IncommingMessage::Request { id, method, .. } => {
+                Err(RequestHandlerError::InvalidParams {
+                    id: id.clone(),
+                    method: method.clone(),
+                })
             }
-            IncommingMessage::Notification { .. } => Err(format!(
-                "TODO: add error types to handler so that they can be gracefully handled outside"
-            )),
-            message => Err(format!("Unhandled message type {:#?}", message)),
+            _ => Ok(RequestHandlerAction::NoopAction),
         };
     }
 
-    pub fn handle_initialize_request(&self, id: u32, client_info: &ClientInfo) -> Response {
-        info!("Connected to: {} {}", client_info.name, client_info.version);
+    pub fn handle_initialize_request(
+        &mut self,
+        id: RequestId,
+        initialize_params: &InitializeParams,
+    ) -> Response {
+        match initialize_params.client_info {
+            Some(ref client_info) => info!(
+                "Connected to: {} {}",
+                client_info.name,
+                client_info.version.as_deref().unwrap_or("")
+            ),
+            None => info!("Connected to unknown client"),
+        }
+        debug!(
+            "Client process id: {:?}, root uri: {:?}",
+            initialize_params.process_id, initialize_params.root_uri
+        );
+        self.state = ServerState::Initialized;
+        self.client_capabilities = initialize_params.capabilities.clone();
+        self.root_uri = initialize_params.root_uri.clone();
+        self.workspace_folders = initialize_params
+            .workspace_folders
+            .clone()
+            .unwrap_or_default();
+        self.trace = initialize_params.trace;
+        self.position_encoding =
+            PositionEncoding::negotiate(&self.client_capabilities.general.position_encodings);
+
+        if let Some(ref initialization_options) = initialize_params.initialization_options {
+            // The settings of the editor, until the client sends newer ones
+            match serde_json::from_value::<Settings>(initialization_options.clone()) {
+                Ok(settings) => self.editor_settings = settings,
+                Err(err) => warn!("Ignoring invalid settings: {}", err),
+            }
+            let initialization_options =
+                serde_json::from_value::<InitializationOptions>(initialization_options.clone())
+                    .unwrap_or_else(|err| {
+                        warn!("Ignoring invalid ticket links: {}", err);
+                        InitializationOptions::default()
+                    });
+            self.document_linker = DocumentLinker::new(
+                initialization_options
+                    .ticket_links
+                    .iter()
+                    .filter_map(|ticket_link| match Regex::new(&ticket_link.pattern) {
+                        Ok(pattern) => Some(TicketLink {
+                            pattern,
+                            url_template: ticket_link.url.clone(),
+                        }),
+                        Err(err) => {
+                            warn!("Ignoring ticket link {}: {}", ticket_link.pattern, err);
+                            None
+                        }
+                    })
+                    .collect(),
+            );
+        }
+
         return Response {
             jsonrpc: constants::JSON_RPC_VERSION,
             id: Some(id),
             result: Some(ResponseResult::InitializeResult {
                 capabilities: ServerCapabilities {
+                    position_encoding: self.position_encoding,
+                    text_document_sync: TextDocumentSyncOptions {
+                        open_close: true,
+                        change: TextDocumentSyncKind::Full,
+                        will_save_wait_until: self
+                            .client_capabilities
+                            .text_document
+                            .synchronization
+                            .will_save_wait_until,
+                    },
                     document_formatting_provider: true,
+                    hover_provider: true,
+                    folding_range_provider: true,
+                    document_link_provider: DocumentLinkOptions {
+                        resolve_provider: false,
+                    },
+                    execute_command_provider: ExecuteCommandOptions {
+                        commands: self.supported_commands(),
+                    },
+                    workspace: WorkspaceServerCapabilities {
+                        workspace_folders: WorkspaceFoldersServerCapabilities {
+                            supported: true,
+                            change_notifications: true,
+                        },
+                    },
                 },
                 server_info: ServerInfo {
                     name: "pycom-wrapper",
@@ -96,9 +614,270 @@ impl RequestHandler {
         };
     }
 
-    pub fn handle_shutdown_request(&mut self, id: u32) -> Response {
+    /// The commands that can be executed, as the wrapping commands need the client to apply
+    /// their edits.
+    fn supported_commands(&self) -> Vec<&'static str> {
+        return match self.client_capabilities.workspace.apply_edit {
+            true => vec![
+                constants::WRAP_FILE_COMMAND,
+                constants::UNWRAP_BLOCK_COMMAND,
+                constants::CHECK_WORKSPACE_COMMAND,
+            ],
+            false => vec![constants::CHECK_WORKSPACE_COMMAND],
+        };
+    }
+
+    /// Asks the client to report changes to configuration files, as their modification time alone
+    /// can miss quick successive edits.
+    pub fn handle_initialized_notification(&self) -> RequestHandlerAction<'static> {
+        if !self
+            .client_capabilities
+            .workspace
+            .did_change_watched_files
+            .dynamic_registration
+        {
+            return RequestHandlerAction::NoopAction;
+        }
+
+        let watchers: Vec<Value> = config::CONFIG_FILE_NAMES
+            .iter()
+            .chain([&config::EDITORCONFIG_FILE_NAME])
+            .map(|file_name| serde_json::json!({ "globPattern": format!("**/{}", file_name) }))
+            .collect();
+
+        return RequestHandlerAction::RequestsAction(vec![self.server_request(
+            ServerRequestParams::RegistrationParams {
+                registrations: vec![Registration {
+                    id: "pycom-wrap/watchConfigFiles".to_string(),
+                    method: "workspace/didChangeWatchedFiles",
+                    register_options: Some(serde_json::json!({ "watchers": watchers })),
+                }],
+            },
+        )]);
+    }
+
+    /// The path of the innermost workspace folder that contains the file at `path`.
+    fn workspace_folder_of(&self, path: &Path) -> Option<PathBuf> {
+        return self
+            .workspace_folders
+            .iter()
+            .filter_map(|workspace_folder| uri::to_path(&workspace_folder.uri))
+            .filter(|folder| path.starts_with(folder))
+            .max_by_key(|folder| folder.components().count());
+    }
+
+    /// The wrapper for the document at `uri`, or `None` if its project excludes it.
+    fn comment_wrapper_for(&self, uri: &str) -> Option<CommentWrapper> {
+        return self
+            .settings_for(uri)
+            .map(|settings| settings.apply(&self.comment_wrapper));
+    }
+
+    /// The settings of the document at `uri`, each taken from the first of the settings pulled
+    /// for it, the settings of the editor, and its project and `.editorconfig` files that sets
+    /// it. Returns `None` if its project excludes it.
+    fn settings_for(&self, uri: &str) -> Option<Settings> {
+        let editor_settings = match self.document_editor_settings.get(uri) {
+            Some(document_editor_settings) => document_editor_settings
+                .clone()
+                .or(self.editor_settings.clone()),
+            None => self.editor_settings.clone(),
+        };
+
+        let project_settings = match uri::to_path(uri) {
+            Some(path) => match self
+                .config_cache
+                .settings(&path, self.workspace_folder_of(&path).as_deref())
+            {
+                Some(project_settings) => project_settings,
+                None => {
+                    debug!("{} is excluded by its project", uri);
+                    return None;
+                }
+            },
+            None => Settings::default(),
+        };
+
+        return Some(editor_settings.or(project_settings));
+    }
+
+    /// Asks the client for its settings for each of `uris`, if it supports that.
+    fn pull_configuration(&self, uris: Vec<String>) -> RequestHandlerAction<'static> {
+        if !self.client_capabilities.workspace.configuration || uris.is_empty() {
+            return RequestHandlerAction::NoopAction;
+        }
+
+        return RequestHandlerAction::RequestsAction(vec![self.server_request(
+            ServerRequestParams::ConfigurationParams {
+                items: uris
+                    .into_iter()
+                    .map(|uri| ConfigurationItem {
+                        scope_uri: Some(uri),
+                        section: Some(constants::CONFIGURATION_SECTION.to_string()),
+                    })
+                    .collect(),
+            },
+        )]);
+    }
+
+    /// Clients that support pulling settings usually leave them out of the notification, so the
+    /// settings of every open document are pulled again.
+    pub fn handle_workspace_did_change_configuration_notification(
+        &mut self,
+        settings: &Value,
+    ) -> RequestHandlerAction<'static> {
+        debug!("Handling did change configuration notification");
+        if let Some(settings) = settings.get(constants::CONFIGURATION_SECTION) {
+            match serde_json::from_value::<Settings>(settings.clone()) {
+                Ok(settings) => self.editor_settings = settings,
+                Err(err) => warn!("Ignoring invalid settings: {}", err),
+            }
+        }
+
+        return self.pull_configuration(self.open_document_uris());
+    }
+
+    /// The folders decide which configuration files apply to the open documents, and clients
+    /// scope their settings by folder, so the settings of every open document are pulled again.
+    pub fn handle_workspace_did_change_workspace_folders_notification(
+        &mut self,
+        event: &WorkspaceFoldersChangeEvent,
+    ) -> RequestHandlerAction<'static> {
+        debug!("Handling did change workspace folders notification");
+        self.workspace_folders.retain(|workspace_folder| {
+            !event
+                .removed
+                .iter()
+                .any(|removed| removed.uri == workspace_folder.uri)
+        });
+        self.workspace_folders.extend(event.added.iter().cloned());
+
+        return self.pull_configuration(self.open_document_uris());
+    }
+
+    fn open_document_uris(&self) -> Vec<String> {
+        let mut uris: Vec<String> = self.documents.keys().cloned().collect();
+        uris.sort();
+
+        return uris;
+    }
+
+    /// Stores the settings of the editor for the documents they were requested for.
+    fn handle_configuration_response(&mut self, items: &[ConfigurationItem], result: Value) {
+        let Ok(results) = serde_json::from_value::<Vec<Value>>(result) else {
+            warn!("Invalid response to workspace/configuration");
+            return;
+        };
+
+        for (item, settings) in items.iter().zip(results) {
+            let Some(ref uri) = item.scope_uri else {
+                continue;
+            };
+            // Clients answer `null` for sections they know nothing about
+            if settings.is_null() {
+                self.document_editor_settings.remove(uri);
+                continue;
+            }
+            match serde_json::from_value::<Settings>(settings) {
+                Ok(settings) => {
+                    self.document_editor_settings.insert(uri.clone(), settings);
+                }
+                Err(err) => warn!("Ignoring invalid settings for {}: {}", uri, err),
+            }
+        }
+    }
+
+    /// Creates a request to the client, and remembers it so that its response can be handled.
+    pub fn server_request(&self, params: ServerRequestParams) -> ServerRequest<'static> {
+        let id = self.next_server_request_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.server_requests
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(id, params.clone());
+
+        return ServerRequest {
+            id,
+            jsonrpc: constants::JSON_RPC_VERSION,
+            method: params.method(),
+            params,
+        };
+    }
+
+    /// Routes the response of the client to the handler of the request it answers.
+    pub fn handle_response(
+        &mut self,
+        id: Option<u32>,
+        result: Option<&Value>,
+        error: Option<&Value>,
+    ) {
+        let params = id.and_then(|id| {
+            self.server_requests
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .remove(&id)
+        });
+        let Some(params) = params else {
+            warn!("Received a response to unknown request {:?}", id);
+            return;
+        };
+        let method = params.method();
+
+        if let Some(error) = error {
+            warn!("Request {} failed on the client: {}", method, error);
+            return;
+        }
+
+        let result = result.cloned().unwrap_or(Value::Null);
+        match params {
+            ServerRequestParams::ApplyWorkspaceEditParams { .. } => {
+                match serde_json::from_value::<ApplyWorkspaceEditResult>(result) {
+                    Ok(ApplyWorkspaceEditResult { applied: true, .. }) => {
+                        debug!("Client applied the edit")
+                    }
+                    Ok(ApplyWorkspaceEditResult { failure_reason, .. }) => warn!(
+                        "Client did not apply the edit: {}",
+                        failure_reason.as_deref().unwrap_or("no reason given")
+                    ),
+                    Err(err) => warn!("Invalid response to {}: {}", method, err),
+                }
+            }
+            ServerRequestParams::ConfigurationParams { items } => {
+                self.handle_configuration_response(&items, result)
+            }
+            ServerRequestParams::ShowMessageRequestParams { .. } => {
+                match serde_json::from_value::<Option<MessageActionItem>>(result) {
+                    Ok(action) => debug!("Client chose action {:?}", action),
+                    Err(err) => warn!("Invalid response to {}: {}", method, err),
+                }
+            }
+            ServerRequestParams::RegistrationParams { .. } => {
+                debug!("Request {} succeeded on the client", method)
+            }
+        }
+    }
+
+    /// Requests that have already been handled can no longer be cancelled, so only the ones that
+    /// are still pending are remembered.
+    pub fn handle_cancel_request_notification(&self, id: RequestId) {
+        debug!("Handling cancel request notification for {}", id);
+        let mut pending_requests = self.pending_requests();
+        if pending_requests.received.contains(&id) {
+            pending_requests.cancelled.insert(id);
+        }
+    }
+
+    /// Returns the exit code of the server, which signals an error unless it was shut down first.
+    pub fn handle_exit_notification(&self) -> i32 {
+        info!("Handling exit notification");
+        return match self.state {
+            ServerState::ShutDown => 0,
+            _ => 1,
+        };
+    }
+
+    pub fn handle_shutdown_request(&mut self, id: RequestId) -> Response {
         info!("Handling shutdown request");
-        self.is_active = false;
+        self.state = ServerState::ShutDown;
         return Response {
             jsonrpc: constants::JSON_RPC_VERSION,
             id: Some(id),
@@ -109,17 +888,1288 @@ impl RequestHandler {
 
     pub fn handle_textdocument_formatting_request(
         &self,
-        id: u32,
+        id: RequestId,
         text_document: &TextDocumentIdentifier,
         optionts: &FormattingOptions,
     ) -> Response {
         info!("Handling formatting request for {}", text_document.uri);
 
+        let document = self.documents.get(&text_document.uri);
+        let comment_wrapper = self.comment_wrapper_for(&text_document.uri);
+        let text_edits = match document.zip(comment_wrapper) {
+            Some((document, comment_wrapper)) => match comment_wrapper.process(&document.text) {
+                Ok(text_edits) => document.text_edits(text_edits),
+                Err(err) => {
+                    warn!("Could not lex {}: {:?}", text_document.uri, err);
+                    return Response {
+                        jsonrpc: constants::JSON_RPC_VERSION,
+                        id: Some(id),
+                        result: None,
+                        error: Some(ResponseError {
+                            code: ResponseErrorCode::RequestFailed,
+                            data: None,
+                            message: "Could not lex document",
+                        }),
+                    };
+                }
+            },
+            None => vec![],
+        };
+
+        return Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id: Some(id),
+            result: Some(ResponseResult::TextEdits(text_edits)),
+            error: None,
+        };
+    }
+
+    pub fn handle_textdocument_will_save_wait_until_request(
+        &self,
+        id: RequestId,
+        text_document: &TextDocumentIdentifier,
+    ) -> Response<'_> {
+        info!(
+            "Handling will save wait until request for {}",
+            text_document.uri
+        );
+
+        // Saving must never be held up by the wrapper, so failures simply result in no edits
+        let document = self.documents.get(&text_document.uri);
+        let settings = self.settings_for(&text_document.uri);
+        let text_edits = match document.zip(settings) {
+            Some((document, settings)) if settings.format_on_save != Some(false) => document
+                .text_edits(
+                    settings
+                        .apply(&self.comment_wrapper)
+                        .process(&document.text)
+                        .unwrap_or_default(),
+                ),
+            _ => vec![],
+        };
+
         return Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id: Some(id),
+            result: Some(ResponseResult::TextEdits(text_edits)),
+            error: None,
+        };
+    }
+
+    pub fn handle_textdocument_hover_request(
+        &self,
+        id: RequestId,
+        text_document: &TextDocumentIdentifier,
+        position: &Position,
+    ) -> Response<'_> {
+        debug!("Handling hover request for {}", text_document.uri);
+
+        let response = Response {
             jsonrpc: constants::JSON_RPC_VERSION,
             id: Some(id),
             result: None,
             error: None,
         };
+
+        let Some(document) = self.documents.get(&text_document.uri) else {
+            return response;
+        };
+        let Some(comment_wrapper) = self.comment_wrapper_for(&text_document.uri) else {
+            return response;
+        };
+        let source = &document.text;
+        let Ok(blocks) = comment_wrapper.comment_blocks(source) else {
+            return response;
+        };
+
+        let offset = document.offset(*position);
+        let Some(block) = blocks
+            .iter()
+            .find(|block| block.range.contains_inclusive(offset))
+        else {
+            return response;
+        };
+
+        let preview = comment_wrapper
+            .wrap_block(source, block)
+            .unwrap_or_else(|| source[block.range].to_string());
+
+        let supports_markdown = match self.client_capabilities.text_document.hover {
+            Some(ref hover) => hover.content_format.iter().any(|w| w == "markdown"),
+            None => false,
+        };
+        let (kind, fence_start, fence_end) = match supports_markdown {
+            true => ("markdown", "```python\n", "\n```"),
+            false => ("plaintext", "", ""),
+        };
+
+        let value = format!(
+            "Width: {} (allowed: {})\n\n{}{}{}{}",
+            block.width(source, comment_wrapper.tab_width),
+            comment_wrapper.wrap_length(),
+            fence_start,
+            block.indent(source),
+            preview,
+            fence_end
+        );
+
+        return Response {
+            result: Some(ResponseResult::Hover {
+                contents: MarkupContent { kind, value },
+                range: document.range(block.range),
+            }),
+            ..response
+        };
+    }
+
+    pub fn handle_textdocument_folding_range_request(
+        &self,
+        id: RequestId,
+        text_document: &TextDocumentIdentifier,
+    ) -> Response<'_> {
+        debug!("Handling folding range request for {}", text_document.uri);
+
+        let blocks = match self.documents.get(&text_document.uri) {
+            Some(document) => self
+                .comment_wrapper
+                .comment_blocks(&document.text)
+                .map(|blocks| (document, blocks))
+                .ok(),
+            None => None,
+        };
+
+        let folding_ranges = match blocks {
+            Some((document, blocks)) => blocks
+                .iter()
+                .map(|block| document.range(block.range))
+                .filter(|range| range.start.line < range.end.line)
+                .map(|range| FoldingRange {
+                    start_line: range.start.line,
+                    end_line: range.end.line,
+                    kind: "comment",
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        return Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id: Some(id),
+            result: Some(ResponseResult::FoldingRanges(folding_ranges)),
+            error: None,
+        };
+    }
+
+    pub fn handle_textdocument_document_link_request(
+        &self,
+        id: RequestId,
+        text_document: &TextDocumentIdentifier,
+    ) -> Response<'_> {
+        debug!("Handling document link request for {}", text_document.uri);
+
+        let document_links = match self.documents.get(&text_document.uri) {
+            Some(document) => match self
+                .document_linker
+                .find_links(&document.text, &self.comment_wrapper)
+            {
+                Ok(links) => links
+                    .into_iter()
+                    .map(|(text_range, target)| DocumentLink {
+                        range: document.range(text_range),
+                        target,
+                    })
+                    .collect(),
+                Err(_) => vec![],
+            },
+            None => vec![],
+        };
+
+        return Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id: Some(id),
+            result: Some(ResponseResult::DocumentLinks(document_links)),
+            error: None,
+        };
+    }
+
+    pub fn handle_workspace_execute_command_request(
+        &self,
+        id: RequestId,
+        command: &str,
+        arguments: &[Value],
+    ) -> RequestHandlerAction<'_> {
+        info!("Executing command {}", command);
+
+        let response = Response {
+            jsonrpc: constants::JSON_RPC_VERSION,
+            id: Some(id),
+            result: None,
+            error: None,
+        };
+
+        let edit = match command {
+            _ if !self.supported_commands().contains(&command) => Err(ResponseError {
+                code: ResponseErrorCode::InvalidParams,
+                data: None,
+                message: "Unsupported command",
+            }),
+            constants::WRAP_FILE_COMMAND => self.wrap_file_edit(arguments),
+            constants::UNWRAP_BLOCK_COMMAND => self.unwrap_block_edit(arguments),
+            constants::CHECK_WORKSPACE_COMMAND => {
+                return RequestHandlerAction::ResponseAction(Response {
+                    result: Some(ResponseResult::Uris(self.check_workspace())),
+                    ..response
+                })
+            }
+            _ => Err(ResponseError {
+                code: ResponseErrorCode::InvalidParams,
+                data: None,
+                message: "Unknown command",
+            }),
+        };
+
+        return match edit {
+            Ok((uri, text_edits)) if !text_edits.is_empty() => {
+                RequestHandlerAction::ResponseWithRequestsAction(
+                    response,
+                    vec![
+                        self.server_request(ServerRequestParams::ApplyWorkspaceEditParams {
+                            label: format!("pycom-wrap: {}", command),
+                            edit: WorkspaceEdit {
+                                changes: HashMap::from([(uri, text_edits)]),
+                            },
+                        }),
+                    ],
+                )
+            }
+            Ok(_) => RequestHandlerAction::ResponseAction(response),
+            Err(error) => RequestHandlerAction::ResponseAction(Response {
+                error: Some(error),
+                ..response
+            }),
+        };
+    }
+
+    fn wrap_file_edit(
+        &self,
+        arguments: &[Value],
+    ) -> Result<(String, Vec<TextEdit>), ResponseError<'static>> {
+        let (uri, document) = self.document_argument(arguments)?;
+        let Some(comment_wrapper) = self.comment_wrapper_for(uri) else {
+            return Ok((uri.to_string(), vec![]));
+        };
+        let text_edits = comment_wrapper
+            .process(&document.text)
+            .map_err(|_| ResponseError {
+                code: ResponseErrorCode::RequestFailed,
+                data: None,
+                message: "Could not lex document",
+            })?;
+
+        return Ok((uri.to_string(), document.text_edits(text_edits)));
+    }
+
+    fn unwrap_block_edit(
+        &self,
+        arguments: &[Value],
+    ) -> Result<(String, Vec<TextEdit>), ResponseError<'static>> {
+        let (uri, document) = self.document_argument(arguments)?;
+        let source = &document.text;
+        let position = arguments
+            .get(1)
+            .and_then(|w| serde_json::from_value::<Position>(w.clone()).ok())
+            .ok_or(ResponseError {
+                code: ResponseErrorCode::InvalidParams,
+                data: None,
+                message: "Expected a position as second argument",
+            })?;
+        let Some(comment_wrapper) = self.comment_wrapper_for(uri) else {
+            return Ok((uri.to_string(), vec![]));
+        };
+        let blocks = comment_wrapper
+            .comment_blocks(source)
+            .map_err(|_| ResponseError {
+                code: ResponseErrorCode::RequestFailed,
+                data: None,
+                message: "Could not lex document",
+            })?;
+
+        let offset = document.offset(position);
+        let text_edits = blocks
+            .iter()
+            .find(|block| block.range.contains_inclusive(offset))
+            .and_then(|block| {
+                comment_wrapper
+                    .unwrap_block(source, block)
+                    .map(|new_text| document.text_edit(block.range, new_text))
+            })
+            .into_iter()
+            .collect();
+
+        return Ok((uri.to_string(), text_edits));
+    }
+
+    /// The documents in the workspace folders whose comments would be rewrapped, with their
+    /// contents in the editor if they are open, and their contents on disk otherwise.
+    fn check_workspace(&self) -> Vec<String> {
+        let would_rewrap = |uri: &str, text: &str| match self.comment_wrapper_for(uri) {
+            Some(comment_wrapper) => comment_wrapper
+                .process(text)
+                .map(|text_edits| !text_edits.is_empty())
+                .unwrap_or(false),
+            None => false,
+        };
+
+        let mut uris: Vec<String> = self
+            .documents
+            .iter()
+            .filter(|(uri, document)| would_rewrap(uri, &document.text))
+            .map(|(uri, _)| uri.clone())
+            .collect();
+
+        let open_paths: HashSet<PathBuf> = self
+            .documents
+            .keys()
+            .filter_map(|uri| uri::to_path(uri))
+            .collect();
+        let folders: Vec<PathBuf> = match self.workspace_folders.is_empty() {
+            true => self
+                .root_uri
+                .iter()
+                .filter_map(|w| uri::to_path(w))
+                .collect(),
+            false => self
+                .workspace_folders
+                .iter()
+                .filter_map(|workspace_folder| uri::to_path(&workspace_folder.uri))
+                .collect(),
+        };
+        for file in format::walk(&folders) {
+            let path = match file {
+                Ok(path) if !open_paths.contains(&path) => path,
+                Ok(_) => continue,
+                Err(err) => {
+                    warn!("{}", err);
+                    continue;
+                }
+            };
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(err) => {
+                    warn!("Could not read {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            let uri = uri::from_path(&path);
+            if would_rewrap(&uri, &text) {
+                uris.push(uri);
+            }
+        }
+        uris.sort();
+        uris.dedup();
+
+        return uris;
+    }
+
+    fn document_argument<'a>(
+        &'a self,
+        arguments: &'a [Value],
+    ) -> Result<(&'a str, &'a Document), ResponseError<'static>> {
+        let uri = arguments
+            .first()
+            .and_then(|w| w.as_str())
+            .ok_or(ResponseError {
+                code: ResponseErrorCode::InvalidParams,
+                data: None,
+                message: "Expected a document uri as first argument",
+            })?;
+        let document = self.documents.get(uri).ok_or(ResponseError {
+            code: ResponseErrorCode::InvalidParams,
+            data: None,
+            message: "Document is not open",
+        })?;
+
+        return Ok((uri, document));
+    }
+
+    pub fn handle_textdocument_did_open_notification(&mut self, text_document: &TextDocumentItem) {
+        debug!("Opened {}", text_document.uri);
+        self.documents.insert(
+            text_document.uri.clone(),
+            Arc::new(Document::new(
+                text_document.text.clone(),
+                self.position_encoding,
+            )),
+        );
+    }
+
+    pub fn handle_textdocument_did_change_notification(
+        &mut self,
+        text_document: &VersionedTextDocumentIdentifier,
+        content_changes: &[TextDocumentContentChangeEvent],
+    ) {
+        debug!("Changed {}", text_document.uri);
+        if let Some(change) = content_changes.last() {
+            self.documents.insert(
+                text_document.uri.clone(),
+                Arc::new(Document::new(change.text.clone(), self.position_encoding)),
+            );
+        }
+    }
+
+    /// Changed configuration files are read again the next time they are needed.
+    pub fn handle_workspace_did_change_watched_files_notification(&self, changes: &[FileEvent]) {
+        for change in changes {
+            debug!(
+                "Watched file {} changed ({})",
+                change.uri, change.change_type
+            );
+            if let Some(path) = uri::to_path(&change.uri) {
+                self.config_cache.invalidate(&path);
+            }
+        }
+    }
+
+    pub fn handle_textdocument_did_close_notification(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+    ) {
+        debug!("Closed {}", text_document.uri);
+        self.documents.remove(&text_document.uri);
+        self.document_editor_settings.remove(&text_document.uri);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestHandler, RequestHandlerAction, RequestHandlerError, ServerState};
+    use crate::config::Settings;
+    use crate::lsp::comment_wrapper::DocstringStyle;
+    use crate::lsp::request::IncommingMessage;
+    use crate::lsp::request::RequestId;
+    use crate::lsp::request::TraceValue;
+    use crate::lsp::server_request::{MessageActionItem, MessageType, ServerRequestParams};
+
+    fn handle(request_handler: &mut RequestHandler, message: &str) -> Option<serde_json::Value> {
+        let message: IncommingMessage = serde_json::from_str(message).unwrap();
+        return match request_handler.handle_request(&message) {
+            Ok(RequestHandlerAction::ResponseAction(response)) => {
+                Some(serde_json::to_value(&response).unwrap())
+            }
+            _ => None,
+        };
+    }
+
+    fn request_error(request_handler: &mut RequestHandler, message: &str) -> RequestHandlerError {
+        let message: IncommingMessage = serde_json::from_str(message).unwrap();
+        return match request_handler.handle_request(&message) {
+            Err(err) => err,
+            Ok(_) => panic!("Expected an error"),
+        };
+    }
+
+    fn initialized_request_handler() -> RequestHandler {
+        let mut request_handler = RequestHandler::new();
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{"workspace":{"applyEdit":true},"textDocument":{"hover":{"contentFormat":["markdown"]},"synchronization":{"willSaveWaitUntil":true}}}}}"#,
+        );
+        return request_handler;
+    }
+
+    fn open_document(request_handler: &mut RequestHandler, text: &str) {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": "file:///a.py",
+                    "languageId": "python",
+                    "version": 1,
+                    "text": text,
+                },
+            },
+        });
+        handle(request_handler, &message.to_string());
+    }
+
+    #[test]
+    fn test_hover_previews_wrapped_comment() {
+        let mut request_handler = initialized_request_handler();
+        request_handler.comment_wrapper.max_line_length = 12;
+
+        open_document(&mut request_handler, "x = 1\n# aaa bbb ccc\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.py"},"position":{"line":1,"character":3}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response["result"]["contents"]["value"],
+            "Width: 13 (allowed: 12)\n\n```python\n# aaa bbb\n# ccc\n```"
+        );
+        assert_eq!(response["result"]["range"]["start"]["line"], 1);
+        assert_eq!(response["result"]["range"]["end"]["character"], 13);
+    }
+
+    #[test]
+    fn test_folding_ranges() {
+        let mut request_handler = initialized_request_handler();
+
+        open_document(
+            &mut request_handler,
+            "# a\n# b\nx = 1\n# c\ndef f():\n    \"\"\"Doc\n    string.\"\"\"\n",
+        );
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/foldingRange","params":{"textDocument":{"uri":"file:///a.py"}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response["result"],
+            serde_json::json!([
+                {"startLine": 0, "endLine": 1, "kind": "comment"},
+                {"startLine": 5, "endLine": 6, "kind": "comment"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_document_links_from_initialization_options() {
+        let mut request_handler = RequestHandler::new();
+
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{},"initializationOptions":{"ticketLinks":[{"pattern":"GH-(\\d+)","url":"https://github.com/org/repo/issues/$1"}]}}}"#,
+        );
+        open_document(&mut request_handler, "# Fixes GH-12\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/documentLink","params":{"textDocument":{"uri":"file:///a.py"}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response["result"],
+            serde_json::json!([{
+                "range": {
+                    "start": {"line": 0, "character": 8},
+                    "end": {"line": 0, "character": 13},
+                },
+                "target": "https://github.com/org/repo/issues/12",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_invalid_initialization_options() {
+        let mut request_handler = RequestHandler::new();
+
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{},"initializationOptions":{"docstringStyle":"Google","lineLength":12,"ticketLinks":[{"pattern":"GH-(\\d+)","url":"https://github.com/org/repo/issues/$1"}]}}}"#,
+        )
+        .unwrap();
+        assert!(response.get("error").is_none());
+        assert_eq!(request_handler.state, ServerState::Initialized);
+        assert_eq!(request_handler.editor_settings, Settings::default());
+
+        open_document(&mut request_handler, "# Fixes GH-12\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/documentLink","params":{"textDocument":{"uri":"file:///a.py"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response["result"][0]["target"],
+            "https://github.com/org/repo/issues/12"
+        );
+    }
+
+    #[test]
+    fn test_execute_wrap_file_command() {
+        let mut request_handler = initialized_request_handler();
+        request_handler.comment_wrapper.max_line_length = 12;
+
+        open_document(&mut request_handler, "# aaa bbb ccc\n");
+        let message: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"workspace/executeCommand","params":{"command":"pycomWrap.wrapFile","arguments":["file:///a.py"]}}"#,
+        )
+        .unwrap();
+
+        let Ok(RequestHandlerAction::ResponseWithRequestsAction(response, requests)) =
+            request_handler.handle_request(&message)
+        else {
+            panic!("Expected a workspace/applyEdit request");
+        };
+
+        assert!(serde_json::to_value(&response).unwrap()["result"].is_null());
+        assert_eq!(
+            serde_json::to_value(&requests).unwrap(),
+            serde_json::json!([{
+                "id": 1,
+                "jsonrpc": "2.0",
+                "method": "workspace/applyEdit",
+                "params": {
+                    "label": "pycom-wrap: pycomWrap.wrapFile",
+                    "edit": {
+                        "changes": {
+                            "file:///a.py": [{
+                                "range": {
+                                    "start": {"line": 0, "character": 0},
+                                    "end": {"line": 0, "character": 13},
+                                },
+                                "newText": "# aaa bbb\n# ccc",
+                            }],
+                        },
+                    },
+                },
+            }])
+        );
+    }
+
+    #[test]
+    fn test_responses_are_routed_to_server_requests() {
+        let mut request_handler = initialized_request_handler();
+        let request =
+            request_handler.server_request(ServerRequestParams::ShowMessageRequestParams {
+                message_type: MessageType::Info,
+                message: "Wrap?".to_string(),
+                actions: vec![MessageActionItem {
+                    title: "Yes".to_string(),
+                }],
+            });
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "id": 1,
+                "jsonrpc": "2.0",
+                "method": "window/showMessageRequest",
+                "params": {"type": 3, "message": "Wrap?", "actions": [{"title": "Yes"}]},
+            })
+        );
+
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{"title":"Yes"}}"#;
+        assert!(handle(&mut request_handler, response).is_none());
+        assert!(request_handler.server_requests.lock().unwrap().is_empty());
+
+        // Responses to unknown requests, or without an id, are ignored
+        assert!(handle(&mut request_handler, response).is_none());
+        assert!(handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Parse error"}}"#
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_project_configuration() {
+        let mut request_handler = RequestHandler::new();
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{"workspace":{"didChangeWatchedFiles":{"dynamicRegistration":true}}}}}"#,
+        );
+        let initialized: IncommingMessage =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#)
+                .unwrap();
+        match request_handler.handle_request(&initialized) {
+            Ok(RequestHandlerAction::RequestsAction(requests)) => assert_eq!(
+                serde_json::to_value(&requests[0]).unwrap()["params"]["registrations"][0]["method"],
+                "workspace/didChangeWatchedFiles"
+            ),
+            _ => panic!("Expected the config files to be watched"),
+        }
+
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-handler-{}-project", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config_file = directory.join("pycom-wrap.toml");
+        std::fs::write(&config_file, "line-length = 12\nexclude = [\"gen_*.py\"]\n").unwrap();
+
+        let uri = |file_name: &str| format!("file://{}/{}", directory.display(), file_name);
+        let formatting = |request_handler: &mut RequestHandler, file_name: &str| {
+            handle(
+                request_handler,
+                &serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/didOpen",
+                    "params": {
+                        "textDocument": {
+                            "uri": uri(file_name),
+                            "languageId": "python",
+                            "version": 1,
+                            "text": "# aaa bbb ccc\n",
+                        },
+                    },
+                })
+                .to_string(),
+            );
+            let response = handle(
+                request_handler,
+                &serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "textDocument/formatting",
+                    "params": {"textDocument": {"uri": uri(file_name)}, "options": {}},
+                })
+                .to_string(),
+            )
+            .unwrap();
+            return response["result"].as_array().unwrap().len();
+        };
+
+        assert_eq!(formatting(&mut request_handler, "a.py"), 1);
+        assert_eq!(formatting(&mut request_handler, "gen_a.py"), 0);
+
+        std::fs::write(&config_file, "line-length = 79\nexclude = [\"gen_*.py\"]\n").unwrap();
+        handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "workspace/didChangeWatchedFiles",
+                "params": {"changes": [{"uri": uri("pycom-wrap.toml"), "type": 2}]},
+            })
+            .to_string(),
+        );
+        assert_eq!(formatting(&mut request_handler, "a.py"), 0);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_workspace_folders_keep_their_own_configuration() {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-handler-{}-folders", std::process::id()));
+        std::fs::create_dir_all(directory.join("a")).unwrap();
+        std::fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 12\n",
+        )
+        .unwrap();
+        let folder = |name: &str| serde_json::json!({"uri": format!("file://{}{}", directory.display(), name), "name": name});
+
+        let mut request_handler = RequestHandler::new();
+        let response = handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": {"capabilities": {}, "workspaceFolders": [folder("/a")]},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            response["result"]["capabilities"]["workspace"]["workspaceFolders"],
+            serde_json::json!({"supported": true, "changeNotifications": true})
+        );
+        let uri = format!("file://{}/a/b.py", directory.display());
+        handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "python",
+                        "version": 1,
+                        "text": "# aaa bbb ccc\n",
+                    },
+                },
+            })
+            .to_string(),
+        );
+        let formatting = |request_handler: &mut RequestHandler| {
+            let response = handle(
+                request_handler,
+                &serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "textDocument/formatting",
+                    "params": {"textDocument": {"uri": uri}, "options": {}},
+                })
+                .to_string(),
+            )
+            .unwrap();
+            return response["result"].as_array().unwrap().len();
+        };
+
+        // The project above the folder does not apply to it
+        assert_eq!(formatting(&mut request_handler), 0);
+
+        handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "workspace/didChangeWorkspaceFolders",
+                "params": {"event": {"added": [folder("")], "removed": [folder("/a")]}},
+            })
+            .to_string(),
+        );
+        assert_eq!(formatting(&mut request_handler), 1);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_editor_settings() {
+        let mut request_handler = RequestHandler::new();
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{"workspace":{"configuration":true}},"initializationOptions":{"lineLength":12}}}"#,
+        );
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        );
+
+        let did_open: IncommingMessage = serde_json::from_str(
+            r##"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"untitled:a","languageId":"python","version":1,"text":"# aaa bbb ccc\n"}}}"##,
+        )
+        .unwrap();
+        let request_id = match request_handler.handle_request(&did_open) {
+            Ok(RequestHandlerAction::RequestsAction(requests)) => {
+                let request = serde_json::to_value(&requests[0]).unwrap();
+                assert_eq!(request["method"], "workspace/configuration");
+                assert_eq!(
+                    request["params"]["items"],
+                    serde_json::json!([{"scopeUri": "untitled:a", "section": "pycomWrap"}])
+                );
+                request["id"].as_u64().unwrap()
+            }
+            _ => panic!("Expected the settings of the document to be pulled"),
+        };
+
+        let formatting = |request_handler: &mut RequestHandler| {
+            let response = handle(
+                request_handler,
+                r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/formatting","params":{"textDocument":{"uri":"untitled:a"},"options":{}}}"#,
+            )
+            .unwrap();
+            return response["result"].as_array().unwrap().len();
+        };
+        assert_eq!(formatting(&mut request_handler), 1);
+
+        handle(
+            &mut request_handler,
+            &serde_json::json!({"jsonrpc": "2.0", "id": request_id, "result": [{"lineLength": 79}]})
+                .to_string(),
+        );
+        assert_eq!(formatting(&mut request_handler), 0);
+
+        let did_change_configuration: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","method":"workspace/didChangeConfiguration","params":{"settings":null}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request_handler.handle_request(&did_change_configuration),
+            Ok(RequestHandlerAction::RequestsAction(_))
+        ));
+    }
+
+    #[test]
+    fn test_pulled_settings_fall_back_to_initialization_options() {
+        let mut request_handler = RequestHandler::new();
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{"workspace":{"configuration":true}},"initializationOptions":{"lineLength":12}}}"#,
+        );
+        let did_open: IncommingMessage = serde_json::from_str(
+            r##"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"untitled:a","languageId":"python","version":1,"text":"# aaa bbb ccc\n"}}}"##,
+        )
+        .unwrap();
+        let pull = |request_handler: &mut RequestHandler, result: serde_json::Value| {
+            let request_id = match request_handler.handle_request(&did_open) {
+                Ok(RequestHandlerAction::RequestsAction(requests)) => {
+                    serde_json::to_value(&requests[0]).unwrap()["id"].clone()
+                }
+                _ => panic!("Expected the settings of the document to be pulled"),
+            };
+            handle(
+                request_handler,
+                &serde_json::json!({"jsonrpc": "2.0", "id": request_id, "result": [result]})
+                    .to_string(),
+            );
+            return request_handler.comment_wrapper_for("untitled:a").unwrap();
+        };
+
+        let comment_wrapper = pull(
+            &mut request_handler,
+            serde_json::json!({"docstringStyle": "google"}),
+        );
+        assert_eq!(comment_wrapper.max_line_length, 12);
+        assert_eq!(comment_wrapper.docstring_style, DocstringStyle::Google);
+
+        let comment_wrapper = pull(&mut request_handler, serde_json::Value::Null);
+        assert_eq!(comment_wrapper.max_line_length, 12);
+        assert_eq!(comment_wrapper.docstring_style, DocstringStyle::Pep257);
+    }
+
+    #[test]
+    fn test_execute_check_workspace_command() {
+        let mut request_handler = initialized_request_handler();
+        request_handler.comment_wrapper.max_line_length = 12;
+
+        open_document(&mut request_handler, "# aaa bbb ccc\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"workspace/executeCommand","params":{"command":"pycomWrap.checkWorkspace"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response["result"], serde_json::json!(["file:///a.py"]));
+    }
+
+    #[test]
+    fn test_check_workspace_command_walks_workspace_folders() {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-handler-{}-check", std::process::id()));
+        std::fs::create_dir_all(directory.join("generated")).unwrap();
+        std::fs::write(
+            directory.join("pyproject.toml"),
+            "[tool.pycom-wrap]\nline-length = 12\nexclude = [\"generated/*\"]\n",
+        )
+        .unwrap();
+        for name in ["a.py", "b.py", "generated/c.py"] {
+            std::fs::write(directory.join(name), "# aaa bbb ccc\n").unwrap();
+        }
+        std::fs::write(directory.join("d.py"), "# aaa\n").unwrap();
+        let uri = |name: &str| format!("file://{}/{}", directory.display(), name);
+
+        let mut request_handler = RequestHandler::new();
+        handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": {
+                    "capabilities": {},
+                    "workspaceFolders": [{"uri": format!("file://{}", directory.display()), "name": "project"}],
+                },
+            })
+            .to_string(),
+        );
+        // Open documents are checked with their unsaved contents
+        handle(
+            &mut request_handler,
+            &serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {
+                    "textDocument": {
+                        "uri": uri("b.py"),
+                        "languageId": "python",
+                        "version": 1,
+                        "text": "# aaa\n",
+                    },
+                },
+            })
+            .to_string(),
+        );
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"workspace/executeCommand","params":{"command":"pycomWrap.checkWorkspace"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response["result"], serde_json::json!([uri("a.py")]));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_will_save_wait_until() {
+        let mut request_handler = RequestHandler::new();
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"capabilities":{"textDocument":{"synchronization":{"willSaveWaitUntil":true}}},"initializationOptions":{"lineLength":12,"formatOnSave":false}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response["result"]["capabilities"]["textDocumentSync"]["willSaveWaitUntil"],
+            true
+        );
+
+        open_document(&mut request_handler, "# aaa bbb ccc\n");
+        let will_save_wait_until = r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/willSaveWaitUntil","params":{"textDocument":{"uri":"file:///a.py"},"reason":1}}"#;
+
+        let response = handle(&mut request_handler, will_save_wait_until).unwrap();
+        assert_eq!(response["result"], serde_json::json!([]));
+
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","method":"workspace/didChangeConfiguration","params":{"settings":{"pycomWrap":{"lineLength":12,"formatOnSave":true}}}}"#,
+        );
+        let response = handle(&mut request_handler, will_save_wait_until).unwrap();
+        assert_eq!(response["result"][0]["newText"], "# aaa bbb\n# ccc");
+    }
+
+    #[test]
+    fn test_unknown_messages() {
+        let mut request_handler = initialized_request_handler();
+
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/rename","params":{}}"#,
+        );
+        assert!(response.is_none());
+
+        let message: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/rename","params":{}}"#,
+        )
+        .unwrap();
+        let error = request_handler.handle_request(&message).err().unwrap();
+        assert_eq!(
+            serde_json::to_value(error.to_response()).unwrap(),
+            serde_json::json!({
+                "id": 1,
+                "jsonrpc": "2.0",
+                "error": {"code": -32601, "message": "Method not found", "data": "textDocument/rename"},
+            })
+        );
+
+        let message: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"foo":1}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request_handler.handle_request(&message),
+            Err(RequestHandlerError::InvalidParams {
+                id: RequestId::Number(2),
+                ..
+            })
+        ));
+
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didSave","params":{"textDocument":{"uri":"file:///a.py"}}}"#,
+        );
+        assert!(response.is_none());
+    }
+
+    #[test]
+    fn test_capability_negotiation() {
+        let mut request_handler = RequestHandler::new();
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"processId":null,"rootUri":"file:///project","capabilities":{"textDocument":{"synchronization":{"willSave":true}}},"trace":"verbose","workspaceFolders":[{"uri":"file:///project","name":"project"}]}}"#,
+        )
+        .unwrap();
+
+        let capabilities = &response["result"]["capabilities"];
+        assert_eq!(capabilities["textDocumentSync"]["willSaveWaitUntil"], false);
+        assert_eq!(
+            capabilities["executeCommandProvider"]["commands"],
+            serde_json::json!(["pycomWrap.checkWorkspace"])
+        );
+        assert_eq!(request_handler.root_uri.as_deref(), Some("file:///project"));
+        assert_eq!(request_handler.workspace_folders[0].name, "project");
+        assert_eq!(request_handler.trace, TraceValue::Verbose);
+
+        open_document(&mut request_handler, "# a comment\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.py"},"position":{"line":0,"character":0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(response["result"]["contents"]["kind"], "plaintext");
+    }
+
+    #[test]
+    fn test_position_encoding_negotiation() {
+        let mut request_handler = RequestHandler::new();
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{"general":{"positionEncodings":["utf-8","utf-16"]}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response["result"]["capabilities"]["positionEncoding"],
+            "utf-8"
+        );
+
+        request_handler.comment_wrapper.max_line_length = 9;
+        open_document(&mut request_handler, "é = 1\n# åå ææ øø\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.py"},"options":{}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response["result"],
+            serde_json::json!([{
+                "range": {
+                    "start": {"line": 1, "character": 0},
+                    "end": {"line": 1, "character": 16},
+                },
+                "newText": "# åå ææ\n# øø",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_cancel_request() {
+        let request_handler = initialized_request_handler();
+        let formatting: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.py"},"options":{}}}"#,
+        )
+        .unwrap();
+        let cancel: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":1}}"#,
+        )
+        .unwrap();
+
+        // Cancelling a request that has not been received yet does nothing
+        request_handler.look_ahead(&cancel);
+        assert!(request_handler.pending_requests().cancelled.is_empty());
+
+        request_handler.look_ahead(&formatting);
+        request_handler.look_ahead(&cancel);
+        assert!(matches!(
+            request_handler.handle_read_only_request(&formatting),
+            Err(RequestHandlerError::RequestCancelled {
+                id: RequestId::Number(1)
+            })
+        ));
+
+        // Cancelling a request that has been handled does nothing either
+        request_handler.handle_cancel_request_notification(RequestId::Number(1));
+        assert!(request_handler.pending_requests().received.is_empty());
+        assert!(request_handler.pending_requests().cancelled.is_empty());
+        assert!(matches!(
+            request_handler.handle_read_only_request(&formatting),
+            Ok(RequestHandlerAction::ResponseAction(_))
+        ));
+    }
+
+    #[test]
+    fn test_request_ids_are_sent_back_unchanged() {
+        let mut request_handler = initialized_request_handler();
+
+        for id in [
+            serde_json::json!("a-1"),
+            serde_json::json!(5_000_000_000_i64),
+        ] {
+            let response = handle(
+                &mut request_handler,
+                &serde_json::json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/foldingRange", "params": {"textDocument": {"uri": "file:///a.py"}}})
+                    .to_string(),
+            )
+            .unwrap();
+            assert_eq!(response["id"], id);
+        }
+
+        let formatting: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":"b","method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.py"},"options":{}}}"#,
+        )
+        .unwrap();
+        let cancel: IncommingMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":"b"}}"#,
+        )
+        .unwrap();
+        request_handler.look_ahead(&formatting);
+        request_handler.look_ahead(&cancel);
+        let error = request_handler
+            .handle_read_only_request(&formatting)
+            .err()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(error.to_response()).unwrap()["id"],
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_is_unaffected_by_changes() {
+        let mut request_handler = initialized_request_handler();
+        request_handler.comment_wrapper.max_line_length = 12;
+        open_document(&mut request_handler, "# aaa bbb ccc\n");
+
+        let snapshot = request_handler.clone();
+        let did_change = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": "file:///a.py", "version": 2},
+                "contentChanges": [{"text": "# aaa\n"}],
+            },
+        });
+        handle(&mut request_handler, &did_change.to_string());
+        let formatting = r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.py"},"options":{}}}"#;
+
+        let message: IncommingMessage = serde_json::from_str(formatting).unwrap();
+        let Ok(RequestHandlerAction::ResponseAction(response)) =
+            snapshot.handle_read_only_request(&message)
+        else {
+            panic!("Expected a response");
+        };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["result"][0]["newText"],
+            "# aaa bbb\n# ccc"
+        );
+        assert_eq!(
+            handle(&mut request_handler, formatting).unwrap()["result"],
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut request_handler = RequestHandler::new();
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{"workspace":{"applyEdit":true},"textDocument":{"hover":{"contentFormat":["markdown"]},"synchronization":{"willSaveWaitUntil":true}}}}}"#;
+        let hover = r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.py"},"position":{"line":0,"character":0}}}"#;
+
+        let response = request_error(&mut request_handler, hover);
+        assert!(matches!(
+            response,
+            RequestHandlerError::ServerNotInitialized {
+                id: RequestId::Number(2)
+            }
+        ));
+        assert_eq!(request_handler.state, ServerState::Uninitialized);
+
+        handle(&mut request_handler, initialize).unwrap();
+        assert_eq!(request_handler.state, ServerState::Initialized);
+        assert!(matches!(
+            request_error(&mut request_handler, initialize),
+            RequestHandlerError::InvalidRequest {
+                id: RequestId::Number(1),
+                ..
+            }
+        ));
+
+        handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        )
+        .unwrap();
+        assert_eq!(request_handler.state, ServerState::ShutDown);
+        assert!(matches!(
+            request_error(&mut request_handler, hover),
+            RequestHandlerError::InvalidRequest {
+                id: RequestId::Number(2),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_hover_outside_comment() {
+        let mut request_handler = initialized_request_handler();
+
+        open_document(&mut request_handler, "x = 1\n");
+        let response = handle(
+            &mut request_handler,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.py"},"position":{"line":0,"character":0}}}"#,
+        )
+        .unwrap();
+
+        assert!(response["result"].is_null());
     }
 }
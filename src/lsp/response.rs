@@ -1,14 +1,34 @@
-use serde::Serialize;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use super::{
+    comment_wrapper::{Range, TextEdit},
+    line_index::PositionEncoding,
+    request::RequestId,
+};
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
 pub struct Response<'a> {
-    pub id: Option<u32>,
+    pub id: Option<RequestId>,
     pub jsonrpc: &'a str,
     pub result: Option<Result>,
     pub error: Option<ResponseError<'a>>,
 }
 
+/// A response has either an error or a result, which is `null` for requests such as `shutdown`
+/// that succeed without one.
+impl Serialize for Response<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut response = serializer.serialize_struct("Response", 3)?;
+        response.serialize_field("id", &self.id)?;
+        response.serialize_field("jsonrpc", self.jsonrpc)?;
+        match self.error {
+            Some(ref error) => response.serialize_field("error", error)?,
+            None => response.serialize_field("result", &self.result)?,
+        }
+
+        return response.end();
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ResponseError<'a> {
@@ -17,8 +37,7 @@ pub struct ResponseError<'a> {
     pub data: Option<ResponseErrorData<'a>>,
 }
 
-#[derive(Serialize)]
-#[serde(untagged)]
+#[derive(Clone, Copy)]
 pub enum ResponseErrorCode {
     ParseError = -32700,
     InvalidRequest = -32600,
@@ -33,6 +52,12 @@ pub enum ResponseErrorCode {
     RequestCancelled = -32800,
 }
 
+impl Serialize for ResponseErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        return serializer.serialize_i32(*self as i32);
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum ResponseErrorData<'a> {
@@ -49,6 +74,37 @@ pub enum Result {
         capabilities: ServerCapabilities,
         server_info: ServerInfo,
     },
+    #[serde(rename_all = "camelCase")]
+    Hover {
+        contents: MarkupContent,
+        range: Range,
+    },
+    TextEdits(Vec<TextEdit>),
+    FoldingRanges(Vec<FoldingRange>),
+    DocumentLinks(Vec<DocumentLink>),
+    Uris(Vec<String>),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentLink {
+    pub range: Range,
+    pub target: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldingRange {
+    pub start_line: u32,
+    pub end_line: u32,
+    pub kind: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkupContent {
+    pub kind: &'static str,
+    pub value: String,
 }
 
 #[derive(Serialize)]
@@ -61,5 +117,58 @@ pub struct ServerInfo {
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ServerCapabilities {
+    pub position_encoding: PositionEncoding,
+    pub text_document_sync: TextDocumentSyncOptions,
     pub document_formatting_provider: bool,
+    pub hover_provider: bool,
+    pub folding_range_provider: bool,
+    pub document_link_provider: DocumentLinkOptions,
+    pub execute_command_provider: ExecuteCommandOptions,
+    pub workspace: WorkspaceServerCapabilities,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceServerCapabilities {
+    pub workspace_folders: WorkspaceFoldersServerCapabilities,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFoldersServerCapabilities {
+    pub supported: bool,
+    pub change_notifications: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteCommandOptions {
+    pub commands: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentLinkOptions {
+    pub resolve_provider: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentSyncOptions {
+    pub open_close: bool,
+    pub change: TextDocumentSyncKind,
+    pub will_save_wait_until: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum TextDocumentSyncKind {
+    None = 0,
+    Full = 1,
+    Incremental = 2,
+}
+
+impl Serialize for TextDocumentSyncKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        return serializer.serialize_u8(*self as u8);
+    }
 }
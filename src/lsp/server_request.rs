@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::comment_wrapper::TextEdit;
+
+/// A request sent from the server to the client.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerRequest<'a> {
+    pub id: u32,
+    pub jsonrpc: &'a str,
+    pub method: &'static str,
+    pub params: ServerRequestParams,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", untagged)]
+pub enum ServerRequestParams {
+    #[serde(rename_all = "camelCase")]
+    ApplyWorkspaceEditParams { label: String, edit: WorkspaceEdit },
+    #[serde(rename_all = "camelCase")]
+    ConfigurationParams { items: Vec<ConfigurationItem> },
+    #[serde(rename_all = "camelCase")]
+    RegistrationParams { registrations: Vec<Registration> },
+    #[serde(rename_all = "camelCase")]
+    ShowMessageRequestParams {
+        #[serde(rename = "type")]
+        message_type: MessageType,
+        message: String,
+        actions: Vec<MessageActionItem>,
+    },
+}
+
+impl ServerRequestParams {
+    pub fn method(&self) -> &'static str {
+        return match self {
+            ServerRequestParams::ApplyWorkspaceEditParams { .. } => "workspace/applyEdit",
+            ServerRequestParams::ConfigurationParams { .. } => "workspace/configuration",
+            ServerRequestParams::RegistrationParams { .. } => "client/registerCapability",
+            ServerRequestParams::ShowMessageRequestParams { .. } => "window/showMessageRequest",
+        };
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceEdit {
+    pub changes: HashMap<String, Vec<TextEdit>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyWorkspaceEditResult {
+    pub applied: bool,
+    pub failure_reason: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationItem {
+    pub scope_uri: Option<String>,
+    pub section: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Registration {
+    pub id: String,
+    pub method: &'static str,
+    pub register_options: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Copy)]
+pub enum MessageType {
+    Error = 1,
+    Warning = 2,
+    Info = 3,
+    Log = 4,
+}
+
+impl Serialize for MessageType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_u8(*self as u8);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageActionItem {
+    pub title: String,
+}
@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+/// The path of a `file://` uri, with its percent-encoded characters decoded.
+pub fn to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    // Skip the authority, which is empty for local files
+    let path = &path[path.find('/')?..];
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match byte {
+            b'%' => tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+
+    // Drive letters come after a slash, as in `file:///c:/project`
+    if cfg!(windows) && path.get(2..3) == Some(":") {
+        return Some(PathBuf::from(&path[1..]));
+    }
+
+    return Some(PathBuf::from(path));
+}
+
+/// The `file://` uri of the absolute `path`, with the characters that uris reserve
+/// percent-encoded.
+pub fn from_path(path: &Path) -> String {
+    let path = match cfg!(windows) {
+        true => path.to_string_lossy().replace('\\', "/"),
+        false => path.to_string_lossy().into_owned(),
+    };
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            // Drive letters keep their colon, as in `file:///c:/project`
+            b':' if uri.len() == "file:///c".len() => uri.push(':'),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    return uri;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_path, to_path};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_to_path() {
+        assert_eq!(
+            to_path("file:///home/a%20b/%C3%A9.py"),
+            Some(PathBuf::from("/home/a b/é.py"))
+        );
+        assert_eq!(
+            to_path("file://localhost/a.py"),
+            Some(PathBuf::from("/a.py"))
+        );
+        assert_eq!(to_path("file:///100%.py"), Some(PathBuf::from("/100%.py")));
+        assert_eq!(to_path("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            from_path(Path::new("/home/a b/é.py")),
+            "file:///home/a%20b/%C3%A9.py"
+        );
+        assert_eq!(
+            to_path(&from_path(Path::new("/100%/a#b.py"))),
+            Some(PathBuf::from("/100%/a#b.py"))
+        );
+    }
+}
@@ -1,65 +1,341 @@
-mod constants;
-mod lsp;
-mod rpc;
-mod scanner;
-use core::panic;
-use std::{fs::OpenOptions, io::Write};
-
-use lsp::{
-    request_handling::{self, RequestHandler, RequestHandlerAction},
-    response,
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+};
+
+use clap::{Parser, Subcommand};
+use pycom_wrap::{
+    format::{self, cache::Cache, FormatError, Formatter},
+    lsp::comment_wrapper::CommentWrapper,
+    server,
+    transport::Transport,
+    worker_pool::WorkerPool,
 };
 use tracing::{error, event, info, Level};
-use tracing_subscriber::{self, layer::SubscriberExt};
-const LOG_FILE_PATH: &str = "~/workspaces/pycom_wrap/log.txt";
+use tracing_subscriber::{self, fmt::writer::BoxMakeWriter, layer::SubscriberExt};
+
+/// Exit code of checks that found files which would be reformatted.
+const EXIT_WOULD_REFORMAT: i32 = 1;
+/// Exit code of runs that could not handle every file, such as files that do not tokenize.
+const EXIT_ERROR: i32 = 2;
+
+/// Wraps the comments and docstrings of Python files.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The options of the language server, which runs without a command
+    #[command(flatten)]
+    lsp: LspArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the language server, which is also what runs without a command
+    Lsp(LspArgs),
+    /// Wrap the Python files in place
+    Format(FormatArgs),
+    /// Exit with code 1 if any Python file would be reformatted, or 2 if any could not be read
+    Check(FilesArgs),
+}
+
+#[derive(clap::Args)]
+struct LspArgs {
+    /// Wait for a client to connect to this TCP address, instead of using stdio
+    #[arg(long, value_name = "ADDRESS", group = "transport")]
+    listen: Option<String>,
+    /// Connect to a client listening on this TCP address, instead of using stdio
+    #[arg(long, value_name = "ADDRESS", group = "transport")]
+    connect: Option<String>,
+    /// Connect to a client listening on this Unix socket, instead of using stdio
+    #[arg(long, value_name = "PATH", group = "transport")]
+    socket: Option<PathBuf>,
+    /// Append the log to this file, instead of writing it to stderr
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct FormatArgs {
+    #[command(flatten)]
+    files: FilesArgs,
+    /// Print the changes as unified diffs instead of writing them
+    #[arg(long)]
+    diff: bool,
+}
+
+#[derive(clap::Args)]
+struct FilesArgs {
+    /// Files, and directories to search for `.py` and `.pyi` files, or `-` to read a file from
+    /// stdin and write it to stdout
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// The path that the file read from stdin is configured as
+    #[arg(long, value_name = "PATH")]
+    stdin_filename: Option<PathBuf>,
+    /// Wrap every file, instead of skipping the files that were wrapped before
+    #[arg(long)]
+    no_cache: bool,
+}
+
+/// What is done with the files that would be reformatted.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Write,
+    Diff,
+    Check,
+}
+
+impl LspArgs {
+    fn transport(self) -> Transport {
+        return match (self.listen, self.connect, self.socket) {
+            (Some(address), _, _) => Transport::Listen(address),
+            (_, Some(address), _) => Transport::Connect(address),
+            (_, _, Some(path)) => Transport::Socket(path),
+            _ => Transport::Stdio,
+        };
+    }
+}
+
 fn main() {
-    // let _ = std::fs::remove_file(LOG_FILE_PATH);
-
-    let file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(LOG_FILE_PATH)
-        .unwrap();
-    let subscriber = tracing_subscriber::Registry::default()
-        .with(tracing_subscriber::fmt::layer().compact().with_writer(file));
+    let args = Args::parse();
+    let exit_code = match args.command {
+        Some(Command::Format(args)) => match args.diff {
+            true => format(&args.files, Mode::Diff),
+            false => format(&args.files, Mode::Write),
+        },
+        Some(Command::Check(args)) => format(&args, Mode::Check),
+        Some(Command::Lsp(args)) => lsp(args),
+        None => lsp(args.lsp),
+    };
+    std::process::exit(exit_code);
+}
+
+fn format(args: &FilesArgs, mode: Mode) -> i32 {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(Level::WARN)
+        .without_time()
+        .init();
+
+    let mut formatter = Formatter::new();
+    if args.paths.iter().any(|path| path == Path::new("-")) {
+        if args.paths.len() > 1 {
+            eprintln!("error: `-` cannot be combined with other paths");
+            return EXIT_ERROR;
+        }
+        return format_stdin(&formatter, args.stdin_filename.as_deref(), mode);
+    }
+    if args.stdin_filename.is_some() {
+        eprintln!("error: `--stdin-filename` only applies when reading `-`");
+        return EXIT_ERROR;
+    }
+
+    if !args.no_cache {
+        if let Some(directory) = format::cache::default_directory() {
+            formatter = formatter.with_cache(Cache::load(&directory));
+        }
+    }
+    let formatter = Arc::new(formatter);
+    let files = formatter.files(&args.paths);
+
+    let (sender, receiver) = mpsc::channel::<(usize, Result<Option<String>, FormatError>)>();
+    let worker_pool = WorkerPool::new(thread::available_parallelism().map_or(1, |w| w.get()));
+    for (idx, file) in files.iter().enumerate() {
+        let Ok((path, comment_wrapper)) = file else {
+            continue;
+        };
+        let (formatter, sender) = (Arc::clone(&formatter), sender.clone());
+        let (path, comment_wrapper) = (path.clone(), comment_wrapper.clone());
+        worker_pool.execute(move || {
+            let result = format_file(&formatter, &path, &comment_wrapper, mode);
+            let _ = sender.send((idx, result));
+        });
+    }
+    drop(sender);
+    // Results arrive in any order, but are reported in the order of the files
+    let mut results: Vec<Option<Result<Option<String>, FormatError>>> =
+        files.iter().map(|_| None).collect();
+    for (idx, result) in receiver {
+        results[idx] = Some(result);
+    }
+    drop(worker_pool);
+
+    let (mut reformatted, mut unchanged, mut failed) = (0, 0, 0);
+    for (file, result) in files.into_iter().zip(results) {
+        match (file, result) {
+            (Ok(_), Some(Ok(None))) => unchanged += 1,
+            (Ok((path, _)), Some(Ok(Some(diff)))) => {
+                match mode {
+                    Mode::Write => eprintln!("reformatted {}", path.display()),
+                    Mode::Diff => print!("{}", diff),
+                    Mode::Check => eprintln!("would reformat {}", path.display()),
+                }
+                reformatted += 1;
+            }
+            (Ok((path, _)), None) => {
+                eprintln!("error: {}: could not be wrapped", path.display());
+                failed += 1;
+            }
+            (Err(err), _) | (_, Some(Err(err))) => {
+                eprintln!("error: {}", err);
+                failed += 1;
+            }
+        }
+    }
+    if let Err(err) = formatter.save_cache() {
+        eprintln!("warning: could not write the cache: {}", err);
+    }
+    eprintln!(
+        "{} {}, {} left unchanged, {} failed",
+        file_count(reformatted),
+        match mode {
+            Mode::Write => "reformatted",
+            Mode::Diff | Mode::Check => "would be reformatted",
+        },
+        file_count(unchanged),
+        file_count(failed)
+    );
+
+    return match (failed, reformatted, mode) {
+        (0, 0, _) | (0, _, Mode::Write | Mode::Diff) => 0,
+        (0, _, Mode::Check) => EXIT_WOULD_REFORMAT,
+        _ => EXIT_ERROR,
+    };
+}
+
+/// Formats the file at `path` with `comment_wrapper` as `mode` says. Returns `None` if it would
+/// not change, or else its diff in `Mode::Diff`.
+fn format_file(
+    formatter: &Formatter,
+    path: &Path,
+    comment_wrapper: &CommentWrapper,
+    mode: Mode,
+) -> Result<Option<String>, FormatError> {
+    if mode == Mode::Write {
+        return Ok(formatter
+            .format_file(path, comment_wrapper)?
+            .then(String::new));
+    }
+
+    let (text, new_text) = formatter.read_and_format(path, comment_wrapper)?;
+    return Ok(match (text == new_text, mode) {
+        (true, _) => None,
+        (false, Mode::Diff) => Some(format::diff(path, &text, &new_text)),
+        (false, _) => Some(String::new()),
+    });
+}
+
+/// Formats stdin as a file at `stdin_filename`, or else as a file in the current directory.
+fn format_stdin(formatter: &Formatter, stdin_filename: Option<&Path>, mode: Mode) -> i32 {
+    let path = stdin_filename.unwrap_or(Path::new("-"));
+    let text = match io::read_to_string(io::stdin()) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("error: could not read stdin: {}", err);
+            return EXIT_ERROR;
+        }
+    };
+
+    let (new_text, exit_code) = match formatter.format_text(path, &text) {
+        Ok(new_text) => (new_text, 0),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            // Filters replace their input with the output, which must not get lost
+            (text.clone(), EXIT_ERROR)
+        }
+    };
+    let output = match mode {
+        Mode::Write => new_text.clone(),
+        Mode::Diff => format::diff(path, &text, &new_text),
+        Mode::Check => String::new(),
+    };
+    if let Err(err) = io::stdout().write_all(output.as_bytes()) {
+        eprintln!("error: could not write stdout: {}", err);
+        return EXIT_ERROR;
+    }
+
+    return match (exit_code, mode) {
+        (0, Mode::Check) if new_text != text => EXIT_WOULD_REFORMAT,
+        _ => exit_code,
+    };
+}
+
+fn file_count(count: usize) -> String {
+    return match count {
+        1 => "1 file".to_string(),
+        _ => format!("{} files", count),
+    };
+}
+
+fn lsp(args: LspArgs) -> i32 {
+    let subscriber =
+        tracing_subscriber::Registry::default().with(log_layer(args.log_file.as_deref()));
     let _ = tracing::subscriber::set_global_default(subscriber);
     std::panic::set_hook(Box::new(|pf| {
         error!("{}", pf.to_string());
     }));
 
     event!(Level::INFO, "Starting pycom-wrap...");
-    let reader = std::io::stdin();
-    let scanner = scanner::Scanner::from_reader(reader, &rpc::split_fn);
-    let mut writer = std::io::stdout();
-    let mut request_handler = RequestHandler::new();
+    let (reader, writer) = match args.transport().open() {
+        Ok(connection) => connection,
+        Err(err) => {
+            error!("Could not open transport: {}", err);
+            eprintln!("Could not open transport: {}", err);
+            return 1;
+        }
+    };
+    let exit_code = server::run(reader, writer);
+
+    info!("Exiting pycom-wrap..");
+    return exit_code;
+}
 
-    for scan in scanner {
-        let msg = scan;
+/// Logs to the file at `path`, or to stderr when there is none or it cannot be opened.
+fn log_layer<S>(path: Option<&Path>) -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let layer = tracing_subscriber::fmt::layer().compact();
+    let Some(path) = path else {
+        return layer.with_writer(BoxMakeWriter::new(io::stderr));
+    };
 
-        info!("[Read] {}", std::str::from_utf8(&msg).unwrap());
-        let message =
-            rpc::decode_message(&msg).unwrap_or_else(|w| panic!("Error decoding message: {}", w));
+    return match OpenOptions::new().append(true).create(true).open(path) {
+        Ok(file) => layer.with_ansi(false).with_writer(BoxMakeWriter::new(file)),
+        Err(err) => {
+            eprintln!(
+                "Could not open log file {}, logging to stderr instead: {}",
+                path.display(),
+                err
+            );
+            layer.with_writer(BoxMakeWriter::new(io::stderr))
+        }
+    };
+}
 
-        let action = request_handler
-            .handle_request(&message)
-            .unwrap_or_else(|w| panic!("Error handling request: {}", w));
+#[cfg(test)]
+mod tests {
+    use super::{Args, Command};
+    use clap::Parser;
 
-        match action {
-            RequestHandlerAction::ResponseAction(response) => {
-                let encoded_message = rpc::encode_message(&response)
-                    .unwrap_or_else(|w| panic!("Error encoding message: {}", w));
+    #[test]
+    fn test_lsp_options_without_command() {
+        let args = Args::try_parse_from(["pycom-wrap", "--listen", "127.0.0.1:0"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.lsp.listen.as_deref(), Some("127.0.0.1:0"));
 
-                info!("[Write] {}", std::str::from_utf8(&encoded_message).unwrap());
+        let args = Args::try_parse_from(["pycom-wrap", "lsp", "--socket", "/tmp/a.sock"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Lsp(lsp)) if lsp.socket.is_some()));
 
-                writer
-                    .write(&encoded_message)
-                    .expect("Error when writing to output");
-                writer.flush().expect("Error when flushing writer.")
-            }
-            RequestHandlerAction::ExitAction => break,
-            RequestHandlerAction::NoopAction => (),
-        }
+        assert!(
+            Args::try_parse_from(["pycom-wrap", "--listen", "127.0.0.1:0", "check", "a.py"])
+                .is_err()
+        );
+        assert!(Args::try_parse_from(["pycom-wrap", "--listen", "a", "--connect", "b"]).is_err());
     }
-
-    info!("Exiting pycom-wrap..");
 }
@@ -1,7 +1,5 @@
 use crate::constants::{self};
-use crate::scanner::SplitFnResult;
 use serde_json;
-use tracing::info;
 
 pub fn encode_message<T>(msg: &T) -> Result<Vec<u8>, String>
 where
@@ -40,72 +38,112 @@ pub fn decode_message<'a, T: serde::de::Deserialize<'a>>(msg: &'a [u8]) -> Resul
     }
 }
 
-pub fn split_fn(data: &[u8], start_hint: usize) -> Result<SplitFnResult, String> {
-    let start_index = match data[start_hint..]
-        .windows(constants::CONTENT_LENGTH_LABEL_BYTES.len())
-        .enumerate()
-        .find(|(_, w)| matches!(*w, constants::CONTENT_LENGTH_LABEL_BYTES))
-        .map(|(i, _)| i + start_hint)
-    {
-        Some(value) => value,
-        None => return Ok(SplitFnResult::Searching),
-    };
+/// A field of a message header that the server knows about.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderField {
+    ContentLength(usize),
+    ContentType,
+    Other,
+}
 
-    let delimiter_index = match data[start_index..]
-        .windows(constants::JSON_RPC_DELIMITER_BYTES.len())
-        .enumerate()
-        .find(|(_, w)| matches!(*w, constants::JSON_RPC_DELIMITER_BYTES))
-        .map(|(i, _)| i)
-    {
-        Some(value) => value,
-        None => return Ok(SplitFnResult::SearchingEnd { start: start_index }),
-    };
+/// Splits a `Name: value` line of a header into its name and trimmed value, or returns `None` if
+/// the line is not a header field at all.
+pub fn split_header_field(line: &[u8]) -> Option<(&str, &str)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (name, value) = line.trim_end_matches(['\r', '\n']).split_once(':')?;
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return None;
+    }
 
-    assert!(start_index + constants::CONTENT_LENGTH_LABEL_BYTES.len() < delimiter_index);
+    return Some((name, value.trim()));
+}
 
-    let content_length_res = match std::str::from_utf8(
-        &data[start_hint + constants::CONTENT_LENGTH_LABEL_BYTES.len()..delimiter_index],
-    ) {
-        Ok(content_length_str) => content_length_str.parse::<usize>(),
-        Err(_) => return Err("Could not convert content length bytes to str".to_string()),
+/// Parses a single line of a header. Names are case-insensitive.
+pub fn parse_header_field(line: &[u8]) -> Result<HeaderField, String> {
+    let Some((name, value)) = split_header_field(line) else {
+        return Err(format!(
+            "Invalid header field: {:?}",
+            String::from_utf8_lossy(line)
+        ));
     };
 
-    let content_length = match content_length_res {
-        Ok(content_length) => content_length,
-        Err(_) => return Err("Could not parse content length".to_string()),
+    return match name.to_ascii_lowercase().as_str() {
+        "content-length" => parse_content_length(value).map(HeaderField::ContentLength),
+        "content-type" => check_content_type(value).map(|_| HeaderField::ContentType),
+        _ => Ok(HeaderField::Other),
     };
+}
+
+/// Parses a whole header, fields in any order, and returns the length of the content after it.
+pub fn parse_header(header: &[u8]) -> Result<usize, String> {
+    let mut content_length: Option<usize> = None;
 
-    let content_start_index = delimiter_index + constants::JSON_RPC_DELIMITER_BYTES.len();
+    for line in header.split_inclusive(|w| *w == b'\n') {
+        if matches!(line, b"\r\n" | b"\n") {
+            break;
+        }
 
-    if data[content_start_index..].len() < content_length {
-        return Ok(SplitFnResult::SearchingEnd { start: start_index });
+        if let HeaderField::ContentLength(length) = parse_header_field(line)? {
+            if content_length.is_some_and(|w| w != length) {
+                return Err("Header has conflicting content lengths".to_string());
+            }
+            content_length = Some(length);
+        }
     }
 
-    return Ok(SplitFnResult::Complete {
-        start: start_index,
-        end: content_start_index + content_length,
-    });
+    return content_length.ok_or("Header has no content length".to_string());
 }
 
-pub fn cut_data(data: &[u8]) -> Result<(&[u8], &[u8]), String> {
-    let delimiter_index_option = data
-        .windows(4)
-        .enumerate()
-        .find(|(_, w)| matches!(*w, constants::JSON_RPC_DELIMITER_BYTES))
-        .map(|(i, _)| i);
-
-    return match delimiter_index_option {
-        Some(delimiter_index) => Ok((&data[..delimiter_index], &data[delimiter_index + 4..])),
-        None => Err(format!(
-            "Could not cut data. Got: {}",
-            std::str::from_utf8(data).unwrap()
-        )),
+/// Returns where the header of a message starts in a line that is not a header field, which
+/// happens when the previous message was cut short or had a wrong content length.
+pub fn find_header_start(line: &[u8]) -> Option<usize> {
+    let label = constants::CONTENT_LENGTH_LABEL.trim_end().as_bytes();
+    return line
+        .windows(label.len())
+        .position(|w| w.eq_ignore_ascii_case(label));
+}
+
+fn parse_content_length(value: &str) -> Result<usize, String> {
+    if value.is_empty() || !value.bytes().all(|w| w.is_ascii_digit()) {
+        return Err(format!("Invalid content length: {:?}", value));
+    }
+
+    return match value.parse::<usize>() {
+        Ok(length) if length <= constants::MAX_CONTENT_LENGTH => Ok(length),
+        _ => Err(format!("Content length is too large: {}", value)),
     };
 }
 
+/// Only the charset of the content type matters, as the content is always JSON.
+fn check_content_type(value: &str) -> Result<(), String> {
+    for parameter in value.split(';').skip(1) {
+        let Some((name, charset)) = parameter.split_once('=') else {
+            continue;
+        };
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            continue;
+        }
+
+        // `utf8` is accepted for backwards compatibility, like the specification asks for
+        let charset = charset.trim().trim_matches('"');
+        if !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("utf8") {
+            return Err(format!("Unsupported charset: {}", charset));
+        }
+    }
+
+    return Ok(());
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    return byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte);
+}
+
 #[cfg(test)]
 mod test {
-    use super::{decode_message, encode_message};
+    use super::{
+        decode_message, encode_message, find_header_start, parse_header, parse_header_field,
+        HeaderField,
+    };
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -128,4 +166,48 @@ mod test {
         let actual: EncodingExample = decode_message(message).unwrap();
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(parse_header(b"Content-Length: 16\r\n\r\n"), Ok(16));
+        assert_eq!(
+            parse_header(
+                b"content-type: application/vscode-jsonrpc; charset=utf8\r\nCONTENT-LENGTH:16\r\n\r\n"
+            ),
+            Ok(16)
+        );
+        assert_eq!(
+            parse_header(b"Content-Length: 1\r\nContent-Length: 1\r\n\r\n"),
+            Ok(1)
+        );
+
+        assert!(parse_header(b"Content-Type: application/json\r\n\r\n").is_err());
+        assert!(parse_header(b"Content-Length: 1\r\nContent-Length: 2\r\n\r\n").is_err());
+        assert!(parse_header(b"Content-Length: 99999999999999999999999\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_header_field() {
+        assert_eq!(
+            parse_header_field(b"Content-Length: 10\r\n"),
+            Ok(HeaderField::ContentLength(10))
+        );
+        assert_eq!(
+            parse_header_field(b"X-Unknown: whatever\r\n"),
+            Ok(HeaderField::Other)
+        );
+        assert!(parse_header_field(b"Content-Length: -1\r\n").is_err());
+        assert!(parse_header_field(b"Content-Length: +1\r\n").is_err());
+        assert!(parse_header_field(b"Content-Type: text/plain; charset=latin-1\r\n").is_err());
+        assert!(parse_header_field(b"{\"id\": 1}\r\n").is_err());
+    }
+
+    #[test]
+    fn test_find_header_start() {
+        assert_eq!(
+            find_header_start(b"\"id\":1}content-length: 2\r\n"),
+            Some(7)
+        );
+        assert_eq!(find_header_start(b"\"id\":1}\r\n"), None);
+    }
 }
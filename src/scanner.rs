@@ -1,64 +1,201 @@
-use tracing::{error, event, info, warn, Level};
+use tracing::{error, warn};
 
-use crate::constants::{self};
-use core::time;
-use std::io::{BufRead, BufReader, Read};
+use crate::{constants, rpc};
+use std::io::{self, BufRead, BufReader, Read};
 
-pub enum SplitFnResult {
-    Searching,
-    SearchingEnd { start: usize },
-    Complete { start: usize, end: usize },
+/// Reads the messages of a stream, each framed by a header with its `Content-Length`.
+///
+/// Reads block until a whole message is available, and bytes that were read past the end of a
+/// message are kept for the next one.
+pub struct Scanner<R: Read> {
+    reader: BufReader<R>,
 }
 
-type SplitFn = dyn Fn(&[u8], usize) -> Result<SplitFnResult, String>;
-
-pub struct Scanner<'a, R: Read> {
-    _bufreader: BufReader<R>,
-    _split_fn: &'a SplitFn,
-}
-
-impl<R: Read> Scanner<'_, R> {
-    pub fn from_reader(reader: R, split_fn: &SplitFn) -> Scanner<R> {
+impl<R: Read> Scanner<R> {
+    pub fn from_reader(reader: R) -> Scanner<R> {
         return Scanner {
-            _bufreader: BufReader::new(reader),
-            _split_fn: split_fn,
+            reader: BufReader::new(reader),
         };
     }
-}
 
-impl<R: Read> Iterator for Scanner<'_, R> {
-    type Item = Vec<u8>;
+    /// Reads a line, but no more than `MAX_HEADER_LINE_LENGTH` bytes of it.
+    fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<usize> {
+        return (&mut self.reader)
+            .take(constants::MAX_HEADER_LINE_LENGTH as u64)
+            .read_until(b'\n', line);
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut payload_buffer: Vec<u8> = Vec::new();
-        let mut start_hint: usize = 0;
+    /// Reads the header of the next message, up to and including the empty line that ends it.
+    /// Returns `None` when the stream ends first.
+    fn read_header(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header: Vec<u8> = Vec::new();
 
         loop {
-            let tmp_buffer = self._bufreader.fill_buf().unwrap();
-            let tmp_buffer_len = tmp_buffer.len();
+            let mut line: Vec<u8> = Vec::new();
+            if self.read_line(&mut line)? == 0 {
+                if !header.is_empty() {
+                    warn!("Stream ended in the middle of a header");
+                }
+                return Ok(None);
+            }
 
-            if tmp_buffer_len > 0 {
-                payload_buffer.extend(tmp_buffer);
+            if !line.ends_with(b"\n") && line.len() == constants::MAX_HEADER_LINE_LENGTH {
+                warn!(
+                    "Skipping header line longer than {} bytes",
+                    constants::MAX_HEADER_LINE_LENGTH
+                );
+                while !line.ends_with(b"\n") && !line.is_empty() {
+                    line.clear();
+                    self.read_line(&mut line)?;
+                }
+                header.clear();
+                continue;
             }
 
-            let split_results = (self._split_fn)(&payload_buffer, start_hint);
-            self._bufreader.consume(tmp_buffer_len);
+            if matches!(line.as_slice(), b"\r\n" | b"\n") && !header.is_empty() {
+                header.extend(&line);
+                return Ok(Some(header));
+            }
 
-            match split_results {
-                Ok(SplitFnResult::Complete { start, end }) => {
-                    return Some(payload_buffer[start..end].to_vec());
-                }
-                Ok(SplitFnResult::SearchingEnd { start }) => {
-                    // we have found start but not end of data
-                    start_hint = start;
+            // Anything that is not a header field is left over from a corrupt message, so the
+            // header starts over at the next content length
+            if rpc::split_header_field(&line).is_none() {
+                let Some(start) = rpc::find_header_start(&line) else {
+                    warn!("Skipping corrupt data: {}", String::from_utf8_lossy(&line));
+                    header.clear();
+                    continue;
+                };
+                warn!(
+                    "Skipping corrupt data: {}",
+                    String::from_utf8_lossy(&line[..start])
+                );
+                header.clear();
+                line.drain(..start);
+            }
+            header.extend(&line);
+        }
+    }
+}
+
+impl<R: Read> Iterator for Scanner<R> {
+    type Item = Vec<u8>;
+
+    /// Returns the next message, with its header normalized to only a `Content-Length`.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let header = match self.read_header() {
+                Ok(Some(header)) => header,
+                Ok(None) => return None,
+                Err(err) => {
+                    error!("Error when reading header: {}", err);
+                    return None;
                 }
-                Ok(SplitFnResult::Searching) => {}
-                Err(message) => {
-                    // payload_buffer.clear();
-                    error!(message);
+            };
+
+            let content_length = match rpc::parse_header(&header) {
+                Ok(content_length) => content_length,
+                Err(err) => {
+                    error!(
+                        "Skipping message with invalid header {:?}: {}",
+                        String::from_utf8_lossy(&header),
+                        err
+                    );
+                    continue;
                 }
+            };
+
+            let mut message: Vec<u8> = constants::CONTENT_LENGTH_LABEL_BYTES.to_vec();
+            message.extend(content_length.to_string().as_bytes());
+            message.extend(constants::JSON_RPC_DELIMITER_BYTES);
+
+            // The content is only buffered as it arrives, rather than allocated up front for
+            // whatever length the header claims
+            let header_length = message.len();
+            if let Err(err) = (&mut self.reader)
+                .take(content_length as u64)
+                .read_to_end(&mut message)
+            {
+                error!("Error when reading content: {}", err);
+                return None;
             }
-            std::thread::sleep(time::Duration::from_millis(200));
+            if message.len() - header_length < content_length {
+                error!(
+                    "Stream ended after {} of {} bytes of content",
+                    message.len() - header_length,
+                    content_length
+                );
+                return None;
+            }
+
+            return Some(message);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Scanner;
+    use crate::constants;
+
+    #[test]
+    fn test_messages_are_split() {
+        let stream: &[u8] =
+            b"Content-Length: 2\r\n\r\n{}Content-Length: 7\r\n\r\n[1,2,3]Content-Length: 1";
+        let messages: Vec<Vec<u8>> = Scanner::from_reader(stream).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                b"Content-Length: 2\r\n\r\n{}".to_vec(),
+                b"Content-Length: 7\r\n\r\n[1,2,3]".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncated_content_ends_stream() {
+        let stream: &[u8] = b"Content-Length: 10\r\n\r\n{}";
+        assert_eq!(Scanner::from_reader(stream).next(), None);
+    }
+
+    #[test]
+    fn test_message_without_content_length_is_skipped() {
+        let stream: &[u8] = b"Content-Length: x\r\n\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(
+            Scanner::from_reader(stream).collect::<Vec<Vec<u8>>>(),
+            vec![b"Content-Length: 2\r\n\r\n{}".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_header_fields_in_any_order() {
+        let stream: &[u8] =
+            b"Content-Type: application/vscode-jsonrpc; charset=utf-8\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(
+            Scanner::from_reader(stream).collect::<Vec<Vec<u8>>>(),
+            vec![b"Content-Length: 2\r\n\r\n{}".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_long_header_line_is_skipped() {
+        let mut stream = vec![b'x'; constants::MAX_HEADER_LINE_LENGTH * 3];
+        stream.extend(b"\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(
+            Scanner::from_reader(stream.as_slice()).collect::<Vec<Vec<u8>>>(),
+            vec![b"Content-Length: 2\r\n\r\n{}".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_recovers_from_corrupt_message() {
+        let stream: &[u8] = b"garbage\nContent-Length: 1\r\n\r\n{}Content-Length: 2\r\n\r\n[]";
+        assert_eq!(
+            Scanner::from_reader(stream).collect::<Vec<Vec<u8>>>(),
+            vec![
+                b"Content-Length: 1\r\n\r\n{".to_vec(),
+                b"Content-Length: 2\r\n\r\n[]".to_vec(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use tracing::{error, info, warn};
+
+use crate::{
+    lsp::{
+        request::IncommingMessage,
+        request_handling::{RequestHandler, RequestHandlerAction, RequestHandlerError},
+    },
+    rpc, scanner,
+    worker_pool::WorkerPool,
+};
+
+/// Serves LSP messages read from `reader` until the client exits, and returns the exit code of
+/// the server.
+///
+/// Messages are read on their own thread and dispatched in order. Requests that only read the
+/// state of the server are answered by a pool of workers, so that a slow request does not hold up
+/// the others. Every message to the client is written by a single writer thread.
+pub fn run<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R, writer: W) -> i32 {
+    let (reader_sender, reader_receiver) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for scan in scanner::Scanner::from_reader(reader) {
+            if reader_sender.send(scan).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (writer_sender, writer_receiver) = mpsc::channel::<Vec<u8>>();
+    let writer_thread = thread::spawn(move || write_messages(writer, writer_receiver));
+
+    // At least two workers, so that a slow request never blocks all of them
+    let worker_pool = WorkerPool::new(
+        thread::available_parallelism()
+            .map(|w| w.get())
+            .unwrap_or(1)
+            .max(2),
+    );
+    let exit_code = dispatch(reader_receiver, &writer_sender, &worker_pool);
+
+    // Answer the requests that are still being worked on before the server goes away
+    drop(worker_pool);
+    drop(writer_sender);
+    if writer_thread.join().is_err() {
+        error!("Writer thread panicked");
+    }
+
+    return exit_code;
+}
+
+fn dispatch(
+    receiver: Receiver<Vec<u8>>,
+    writer: &Sender<Vec<u8>>,
+    worker_pool: &WorkerPool,
+) -> i32 {
+    let mut request_handler = Arc::new(RequestHandler::new());
+    let mut queue: VecDeque<Vec<u8>> = VecDeque::new();
+
+    loop {
+        if queue.is_empty() {
+            match receiver.recv() {
+                Ok(msg) => queue.push_back(look_ahead(&request_handler, msg)),
+                Err(_) => break,
+            }
+        }
+        // Messages that arrived while the previous one was handled queue up, so that
+        // cancellations are known before the requests they refer to get their turn
+        while let Ok(msg) = receiver.try_recv() {
+            queue.push_back(look_ahead(&request_handler, msg));
+        }
+
+        let msg = queue.pop_front().expect("Queue is not empty");
+
+        info!("[Read] {}", String::from_utf8_lossy(&msg));
+        let message: IncommingMessage = match rpc::decode_message(&msg) {
+            Ok(message) => message,
+            Err(err) => {
+                error!("Error decoding message: {}", err);
+                send_message(writer, &RequestHandlerError::ParseError(err).to_response());
+                continue;
+            }
+        };
+
+        if RequestHandler::is_read_only(&message) {
+            // The worker gets a snapshot of the handler, which later notifications do not touch
+            let request_handler = Arc::clone(&request_handler);
+            let writer = writer.clone();
+            worker_pool.execute(move || {
+                if let Ok(message) = rpc::decode_message(&msg) {
+                    send_result(&writer, request_handler.handle_read_only_request(&message));
+                }
+            });
+            continue;
+        }
+
+        let result = Arc::make_mut(&mut request_handler).handle_request(&message);
+        if let Some(exit_code) = send_result(writer, result) {
+            return exit_code;
+        }
+    }
+
+    // The client went away without asking the server to exit
+    return 1;
+}
+
+fn look_ahead(request_handler: &RequestHandler, msg: Vec<u8>) -> Vec<u8> {
+    if let Ok(message) = rpc::decode_message(&msg) {
+        request_handler.look_ahead(&message);
+    }
+    return msg;
+}
+
+/// Sends the outcome of handling a message to the client, and returns the exit code if the server
+/// should exit.
+fn send_result(
+    writer: &Sender<Vec<u8>>,
+    result: Result<RequestHandlerAction, RequestHandlerError>,
+) -> Option<i32> {
+    let action = match result {
+        Ok(action) => action,
+        Err(err) => {
+            warn!("Error handling request: {}", err);
+            send_message(writer, &err.to_response());
+            return None;
+        }
+    };
+
+    match action {
+        RequestHandlerAction::ResponseAction(response) => {
+            send_message(writer, &response);
+        }
+        RequestHandlerAction::ResponseWithRequestsAction(response, requests) => {
+            for request in requests.iter() {
+                send_message(writer, request);
+            }
+            send_message(writer, &response);
+        }
+        RequestHandlerAction::RequestsAction(requests) => {
+            for request in requests.iter() {
+                send_message(writer, request);
+            }
+        }
+        RequestHandlerAction::ExitAction(exit_code) => return Some(exit_code),
+        RequestHandlerAction::NoopAction => (),
+    }
+
+    return None;
+}
+
+fn send_message<T: serde::Serialize>(writer: &Sender<Vec<u8>>, message: &T) {
+    match rpc::encode_message(message) {
+        Ok(encoded_message) => {
+            if writer.send(encoded_message).is_err() {
+                error!("Writer thread has stopped");
+            }
+        }
+        Err(err) => error!("Error encoding message: {}", err),
+    }
+}
+
+fn write_messages<W: Write>(mut writer: W, receiver: Receiver<Vec<u8>>) {
+    for encoded_message in receiver {
+        info!("[Write] {}", String::from_utf8_lossy(&encoded_message));
+
+        if let Err(err) = writer
+            .write_all(&encoded_message)
+            .and_then(|_| writer.flush())
+        {
+            error!("Error when writing to output: {}", err);
+        }
+    }
+}
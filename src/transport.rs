@@ -0,0 +1,136 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+};
+
+use tracing::info;
+
+/// The channel the server talks to its client over, which always uses the same framing.
+pub enum Transport {
+    Stdio,
+    /// Waits for a single client to connect to the TCP address.
+    Listen(String),
+    /// Connects to a client listening on the TCP address.
+    Connect(String),
+    /// Connects to a client listening on the Unix socket.
+    Socket(PathBuf),
+}
+
+type Connection = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+impl Transport {
+    /// Opens the transport, blocking until the client is connected.
+    pub fn open(&self) -> io::Result<Connection> {
+        return match self {
+            Transport::Stdio => Ok((Box::new(io::stdin()), Box::new(io::stdout()))),
+            Transport::Listen(address) => {
+                let listener = TcpListener::bind(address)?;
+                info!("Listening on {}", listener.local_addr()?);
+                let (stream, client_address) = listener.accept()?;
+                info!("Accepted client {}", client_address);
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+            Transport::Connect(address) => {
+                let stream = TcpStream::connect(address)?;
+                info!("Connected to {}", address);
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+            Transport::Socket(path) => connect_socket(path),
+        };
+    }
+}
+
+#[cfg(unix)]
+fn connect_socket(path: &PathBuf) -> io::Result<Connection> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    info!("Connected to {}", path.display());
+    return Ok((Box::new(stream.try_clone()?), Box::new(stream)));
+}
+
+#[cfg(not(unix))]
+fn connect_socket(_path: &PathBuf) -> io::Result<Connection> {
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transport;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    fn echo<S: Read + Write>(mut stream: S) {
+        let mut buffer = [0; 5];
+        stream.read_exact(&mut buffer).unwrap();
+        stream.write_all(&buffer).unwrap();
+    }
+
+    fn assert_echoes(transport: Transport) {
+        let (mut reader, mut writer) = transport.open().unwrap();
+        writer.write_all(b"hello").unwrap();
+
+        let mut buffer = [0; 5];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let client = thread::spawn(move || echo(listener.accept().unwrap().0));
+
+        assert_echoes(Transport::Connect(address));
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn test_listen() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let server = thread::spawn({
+            let address = address.clone();
+            move || {
+                let (mut reader, mut writer) = Transport::Listen(address).open().unwrap();
+                let mut buffer = [0; 5];
+                reader.read_exact(&mut buffer).unwrap();
+                writer.write_all(&buffer).unwrap();
+            }
+        });
+
+        let mut stream = (0..100)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(20));
+                TcpStream::connect(&address).ok()
+            })
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut buffer = [0; 5];
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket() {
+        let path = std::env::temp_dir().join(format!("pycom-wrap-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let client = thread::spawn(move || echo(listener.accept().unwrap().0));
+
+        assert_echoes(Transport::Socket(path.clone()));
+        client.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
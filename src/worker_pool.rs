@@ -0,0 +1,117 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads that execute jobs in the order they were submitted.
+pub struct WorkerPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || work(&receiver))
+            })
+            .collect();
+
+        return WorkerPool {
+            sender: Some(sender),
+            workers,
+        };
+    }
+
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if let Some(ref sender) = self.sender {
+            if sender.send(Box::new(job)).is_err() {
+                error!("Worker pool has no workers left");
+            }
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is released before the job runs, so that the other workers can pick up jobs
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+
+        match job {
+            Ok(job) => {
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("Worker job panicked");
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+/// Waits for the jobs that were already submitted to finish.
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerPool;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    };
+
+    #[test]
+    fn test_jobs_finish_before_drop() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let worker_pool = WorkerPool::new(3);
+
+        for _ in 0..10 {
+            let counter = Arc::clone(&counter);
+            worker_pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        worker_pool.execute(|| panic!("Workers survive panicking jobs"));
+        drop(worker_pool);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_slow_job_does_not_block_others() {
+        let worker_pool = WorkerPool::new(2);
+        let (blocker_sender, blocker_receiver) = mpsc::channel::<()>();
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+
+        worker_pool.execute(move || {
+            let _ = blocker_receiver.recv();
+        });
+        worker_pool.execute(move || done_sender.send(()).unwrap());
+
+        assert!(done_receiver
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .is_ok());
+        blocker_sender.send(()).unwrap();
+    }
+}
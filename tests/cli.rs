@@ -0,0 +1,211 @@
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// A project in a temporary directory, which wraps comments at 12 characters.
+struct Project {
+    directory: PathBuf,
+}
+
+impl Project {
+    fn new(name: &str) -> Project {
+        let directory =
+            std::env::temp_dir().join(format!("pycom-wrap-cli-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("src")).unwrap();
+        fs::write(directory.join("pycom-wrap.toml"), "line-length = 12\n").unwrap();
+
+        return Project { directory };
+    }
+
+    fn write(&self, path: &str, text: &str) {
+        fs::write(self.directory.join(path), text).unwrap();
+    }
+
+    fn read(&self, path: &str) -> String {
+        return fs::read_to_string(self.directory.join(path)).unwrap();
+    }
+
+    /// Runs pycom-wrap in the project, and returns its exit code and stdout.
+    fn run(&self, args: &[&str], stdin: Option<&str>) -> (i32, String) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pycom-wrap"))
+            .args(args)
+            .current_dir(&self.directory)
+            .env("PYCOM_WRAP_CACHE_DIR", self.directory.join("cache"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut child_stdin = child.stdin.take().unwrap();
+        child_stdin
+            .write_all(stdin.unwrap_or_default().as_bytes())
+            .unwrap();
+        drop(child_stdin);
+        let output = child.wait_with_output().unwrap();
+
+        return (
+            output.status.code().unwrap(),
+            String::from_utf8(output.stdout).unwrap(),
+        );
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+#[test]
+fn test_check() {
+    let project = Project::new("check");
+    project.write("src/a.py", "# aaa bbb\n");
+
+    assert_eq!(project.run(&["check", "src"], None), (0, String::new()));
+
+    project.write("src/b.py", "# aaa bbb ccc\n");
+    assert_eq!(project.run(&["check", "src"], None), (1, String::new()));
+    assert_eq!(project.read("src/b.py"), "# aaa bbb ccc\n");
+
+    project.write("src/c.py", "x = $\n");
+    assert_eq!(project.run(&["check", "src"], None), (2, String::new()));
+}
+
+#[test]
+fn test_format_diff() {
+    let project = Project::new("diff");
+    project.write("src/a.py", "# aaa bbb\n");
+
+    assert_eq!(
+        project.run(&["format", "--diff", "src"], None),
+        (0, String::new())
+    );
+
+    project.write("src/b.py", "# aaa bbb ccc\n");
+    let diff = "--- src/b.py\n+++ src/b.py\n@@ -1 +1,2 @@\n-# aaa bbb ccc\n+# aaa bbb\n+# ccc\n";
+    assert_eq!(
+        project.run(&["format", "--diff", "src"], None),
+        (0, diff.to_string())
+    );
+    assert_eq!(project.read("src/b.py"), "# aaa bbb ccc\n");
+
+    project.write("src/c.py", "x = $\n");
+    assert_eq!(
+        project.run(&["format", "--diff", "src"], None),
+        (2, diff.to_string())
+    );
+}
+
+#[test]
+fn test_format() {
+    let project = Project::new("format");
+    project.write("src/a.py", "# aaa bbb\n");
+    project.write("src/b.py", "# aaa bbb ccc\n");
+
+    assert_eq!(project.run(&["format", "src"], None), (0, String::new()));
+    assert_eq!(project.read("src/a.py"), "# aaa bbb\n");
+    assert_eq!(project.read("src/b.py"), "# aaa bbb\n# ccc\n");
+    assert_eq!(project.run(&["check", "src"], None), (0, String::new()));
+
+    // Files that tokenize are still wrapped when others do not
+    project.write("src/b.py", "# aaa bbb ccc\n");
+    project.write("src/c.py", "x = $\n");
+    assert_eq!(project.run(&["format", "src"], None), (2, String::new()));
+    assert_eq!(project.read("src/b.py"), "# aaa bbb\n# ccc\n");
+    assert_eq!(project.read("src/c.py"), "x = $\n");
+}
+
+#[test]
+fn test_format_stdin() {
+    let project = Project::new("stdin");
+    project.write(
+        "pycom-wrap.toml",
+        "line-length = 12\nexclude = [\"generated/*\"]\n",
+    );
+
+    assert_eq!(
+        project.run(&["format", "-"], Some("# aaa bbb ccc\n")),
+        (0, "# aaa bbb\n# ccc\n".to_string())
+    );
+    assert_eq!(
+        project.run(
+            &["format", "-", "--stdin-filename", "src/a.py"],
+            Some("# aaa bbb ccc\n")
+        ),
+        (0, "# aaa bbb\n# ccc\n".to_string())
+    );
+    assert_eq!(
+        project.run(
+            &["format", "-", "--stdin-filename", "generated/a.py"],
+            Some("# aaa bbb ccc\n")
+        ),
+        (0, "# aaa bbb ccc\n".to_string())
+    );
+
+    // The input must not get lost when it is replaced by the output
+    assert_eq!(
+        project.run(&["format", "-"], Some("# aaa bbb ccc\nx = $\n")),
+        (2, "# aaa bbb ccc\nx = $\n".to_string())
+    );
+    assert_eq!(
+        project.run(
+            &["format", "-", "--stdin-filename", "src/a.py"],
+            Some("x = $\n")
+        ),
+        (2, "x = $\n".to_string())
+    );
+}
+
+#[test]
+fn test_check_stdin() {
+    let project = Project::new("check-stdin");
+
+    assert_eq!(
+        project.run(&["check", "-"], Some("# aaa bbb\n")),
+        (0, String::new())
+    );
+    assert_eq!(
+        project.run(&["check", "-"], Some("# aaa bbb ccc\n")),
+        (1, String::new())
+    );
+    assert_eq!(
+        project.run(
+            &["check", "-", "--stdin-filename", "src/a.py"],
+            Some("# aaa bbb ccc\n")
+        ),
+        (1, String::new())
+    );
+}
+
+#[test]
+fn test_stdin_filename_without_stdin() {
+    let project = Project::new("stdin-filename");
+    project.write("src/a.py", "# aaa bbb ccc\n");
+
+    assert_eq!(
+        project.run(&["format", "src", "--stdin-filename", "src/a.py"], None),
+        (2, String::new())
+    );
+    assert_eq!(project.read("src/a.py"), "# aaa bbb ccc\n");
+}
+
+#[test]
+fn test_lsp_log_file() {
+    let project = Project::new("lsp-log-file");
+
+    // Without an `exit` notification the server exits with code 1, as the specification asks for
+    assert_eq!(project.run(&["lsp"], None), (1, String::new()));
+    assert_eq!(
+        project.run(&["lsp", "--log-file", "lsp.log"], None),
+        (1, String::new())
+    );
+    assert!(project.read("lsp.log").contains("Starting pycom-wrap"));
+    assert_eq!(
+        project.run(&["lsp", "--log-file", "missing/lsp.log"], None),
+        (1, String::new())
+    );
+}
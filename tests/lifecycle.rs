@@ -0,0 +1,204 @@
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use pycom_wrap::{rpc, server};
+use serde_json::{json, Value};
+
+/// Read end of an in-memory pipe, where every chunk sent into the pipe is read on its own.
+struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        return Ok(len);
+    }
+}
+
+struct PipeWriter {
+    sender: Sender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+struct Client {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    server: JoinHandle<i32>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let (client_sender, server_receiver) = channel();
+        let (server_sender, client_receiver) = channel();
+        let server = thread::spawn(move || {
+            server::run(
+                PipeReader {
+                    receiver: server_receiver,
+                    chunk: vec![],
+                    position: 0,
+                },
+                PipeWriter {
+                    sender: server_sender,
+                },
+            )
+        });
+
+        return Client {
+            sender: client_sender,
+            receiver: client_receiver,
+            server,
+        };
+    }
+
+    fn send(&self, message: Value) {
+        self.send_raw(&rpc::encode_message(&message).unwrap());
+    }
+
+    fn send_raw(&self, message: &[u8]) {
+        self.sender.send(message.to_vec()).unwrap();
+    }
+
+    fn receive(&self) -> Value {
+        let message = self.receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        return rpc::decode_message(&message).unwrap();
+    }
+
+    fn request(&self, id: u32, method: &str, params: Value) -> Value {
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        return self.receive();
+    }
+
+    fn exit(self) -> i32 {
+        self.send(json!({"jsonrpc": "2.0", "method": "exit"}));
+        return self.server.join().unwrap();
+    }
+
+    /// Closes the stream to the server without asking it to exit.
+    fn close(self) -> i32 {
+        drop(self.sender);
+        return self.server.join().unwrap();
+    }
+}
+
+fn initialize_params() -> Value {
+    return json!({
+        "processId": null,
+        "clientInfo": {"name": "test", "version": "1"},
+        "capabilities": {},
+    });
+}
+
+#[test]
+fn test_requests_before_initialize() {
+    let client = Client::start();
+
+    let response = client.request(1, "shutdown", Value::Null);
+    assert_eq!(response["error"]["code"], -32002);
+
+    let response = client.request(2, "initialize", initialize_params());
+    assert_eq!(response["id"], 2);
+    assert!(response["result"]["capabilities"].is_object());
+
+    assert_eq!(client.exit(), 1);
+}
+
+#[test]
+fn test_initialize_twice() {
+    let client = Client::start();
+
+    client.request(1, "initialize", initialize_params());
+    let response = client.request(2, "initialize", initialize_params());
+    assert_eq!(response["error"]["code"], -32600);
+
+    assert_eq!(client.exit(), 1);
+}
+
+#[test]
+fn test_exit_after_shutdown() {
+    let client = Client::start();
+
+    client.request(1, "initialize", initialize_params());
+    client.send(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}));
+    let response = client.request(2, "shutdown", Value::Null);
+    assert_eq!(response, json!({"jsonrpc": "2.0", "id": 2, "result": null}));
+
+    let response = client.request(3, "textDocument/formatting", json!({}));
+    assert_eq!(response["error"]["code"], -32600);
+
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn test_malformed_message() {
+    let client = Client::start();
+
+    client.send_raw(b"Content-Length: 9\r\n\r\n{\"id\": 1,");
+    let response = client.receive();
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    let response = client.request(1, "initialize", initialize_params());
+    assert!(response["result"].is_object());
+
+    assert_eq!(client.exit(), 1);
+}
+
+#[test]
+fn test_messages_in_one_chunk() {
+    let client = Client::start();
+
+    let mut chunk = rpc::encode_message(
+        &json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": initialize_params()}),
+    )
+    .unwrap();
+    chunk.extend(
+        rpc::encode_message(&json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"})).unwrap(),
+    );
+    client.send_raw(&chunk);
+
+    assert_eq!(client.receive()["id"], 1);
+    assert_eq!(client.receive()["id"], 2);
+
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn test_end_of_stream() {
+    let client = Client::start();
+
+    client.request(1, "initialize", initialize_params());
+    client.send_raw(b"Content-Length: 100\r\n\r\n{");
+
+    assert_eq!(client.close(), 1);
+}